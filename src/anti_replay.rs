@@ -0,0 +1,223 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::Duration;
+use super::hash;
+use super::timed_store::TimedStore;
+
+/// The outcome of `AntiReplayWindow::check`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceStatus {
+    /// The sequence number hasn't been seen before and is now recorded.
+    Accept,
+    /// The sequence number has already been seen from this sender.
+    Duplicate,
+    /// The sequence number is too far behind the sender's highest to be checked.
+    TooOld,
+}
+
+/// Per-sender replay protection for monotonically increasing sequence numbers, in the style of
+/// IPsec's anti-replay window.
+///
+/// For each sender, the highest sequence number seen is recorded along with a bitmap of which of
+/// the `width` sequence numbers up to it have been seen.  Numbers beyond the highest are always
+/// accepted and slide the window forwards; numbers which have fallen out of the back of the
+/// window are rejected as too old, since they can no longer be checked.
+///
+/// The senders are bounded by capacity and/or time to live in the same way as the messages in a
+/// `MessageFilter`.  A sender which has been dropped starts afresh, i.e. any of its sequence
+/// numbers would be accepted again, so the bounds should comfortably exceed the lifetime of the
+/// keys authenticating the sequence numbers.
+pub struct AntiReplayWindow<Sender> {
+    windows: TimedStore<SequenceWindow>,
+    width: usize,
+    phantom: PhantomData<Sender>,
+}
+
+impl<Sender: Hash> AntiReplayWindow<Sender> {
+    /// Constructor for capacity based `AntiReplayWindow` with windows `width` sequence numbers
+    /// wide.
+    pub fn with_capacity(width: usize, capacity: usize) -> AntiReplayWindow<Sender> {
+        AntiReplayWindow::new(width, Some(capacity), None)
+    }
+
+    /// Constructor for time based `AntiReplayWindow` with windows `width` sequence numbers wide.
+    pub fn with_expiry_duration(width: usize, time_to_live: Duration) -> AntiReplayWindow<Sender> {
+        AntiReplayWindow::new(width, None, Some(time_to_live))
+    }
+
+    /// Constructor for dual-feature capacity and time based `AntiReplayWindow` with windows
+    /// `width` sequence numbers wide.
+    pub fn with_expiry_duration_and_capacity(width: usize,
+                                             time_to_live: Duration,
+                                             capacity: usize)
+                                             -> AntiReplayWindow<Sender> {
+        AntiReplayWindow::new(width, Some(capacity), Some(time_to_live))
+    }
+
+    /// Checks `sequence_number` from `sender`, recording it if it's accepted.
+    ///
+    /// Either way the sender's expiry time is updated and it is moved to the back of the FIFO
+    /// queue, as with `MessageFilter::insert`.
+    pub fn check(&mut self, sender: &Sender, sequence_number: u64) -> SequenceStatus {
+        let hash_code = hash(sender);
+        let mut window = match self.windows.remove(hash_code) {
+            Some(window) => window,
+            None => SequenceWindow::new(self.width, sequence_number),
+        };
+        let status = window.check(sequence_number);
+        let _ = self.windows.insert(hash_code, window);
+        status
+    }
+
+    /// Returns the number of senders being tracked.
+    pub fn len(&mut self) -> usize {
+        self.windows.remove_expired();
+        self.windows.len()
+    }
+
+    /// Returns whether no senders are being tracked.
+    pub fn is_empty(&mut self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clears all senders' windows.
+    pub fn clear(&mut self) {
+        self.windows.clear();
+    }
+
+    fn new(width: usize,
+           capacity: Option<usize>,
+           time_to_live: Option<Duration>)
+           -> AntiReplayWindow<Sender> {
+        AntiReplayWindow {
+            windows: TimedStore::new(capacity, time_to_live),
+            width: cmp::max(width, 1),
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// The window for a single sender.  Sequence number `n` is recorded in bit `n % width`, where the
+/// width is rounded up to a whole number of words.
+struct SequenceWindow {
+    highest: u64,
+    bits: Vec<u64>,
+}
+
+impl SequenceWindow {
+    /// Creates a window whose first sequence number will be `first`.
+    fn new(width: usize, first: u64) -> SequenceWindow {
+        SequenceWindow {
+            highest: first,
+            bits: vec![0; (width + 63) / 64],
+        }
+    }
+
+    fn width(&self) -> u64 {
+        self.bits.len() as u64 * 64
+    }
+
+    fn check(&mut self, sequence_number: u64) -> SequenceStatus {
+        if sequence_number > self.highest {
+            // Slide the window forwards, clearing the bits of the skipped sequence numbers.
+            let advance = sequence_number - self.highest;
+            if advance >= self.width() {
+                for word in &mut self.bits {
+                    *word = 0;
+                }
+            } else {
+                for skipped in self.highest + 1..sequence_number {
+                    self.set(skipped, false);
+                }
+            }
+            self.highest = sequence_number;
+        } else if self.highest - sequence_number >= self.width() {
+            return SequenceStatus::TooOld;
+        } else if self.get(sequence_number) {
+            return SequenceStatus::Duplicate;
+        }
+        self.set(sequence_number, true);
+        SequenceStatus::Accept
+    }
+
+    fn get(&self, sequence_number: u64) -> bool {
+        let bit = sequence_number % self.width();
+        (self.bits[(bit / 64) as usize] & (1 << (bit % 64))) != 0
+    }
+
+    fn set(&mut self, sequence_number: u64, value: bool) {
+        let bit = sequence_number % self.width();
+        let word = &mut self.bits[(bit / 64) as usize];
+        if value {
+            *word |= 1 << (bit % 64);
+        } else {
+            *word &= !(1 << (bit % 64));
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sliding_window() {
+        let mut window = AntiReplayWindow::<u8>::with_capacity(128, 10);
+        assert_eq!(SequenceStatus::Accept, window.check(&0, 1000));
+        assert_eq!(SequenceStatus::Duplicate, window.check(&0, 1000));
+
+        // Out of order arrivals within the window are accepted once each.
+        assert_eq!(SequenceStatus::Accept, window.check(&0, 999));
+        assert_eq!(SequenceStatus::Accept, window.check(&0, 1010));
+        assert_eq!(SequenceStatus::Accept, window.check(&0, 1005));
+        assert_eq!(SequenceStatus::Duplicate, window.check(&0, 1005));
+        assert_eq!(SequenceStatus::Duplicate, window.check(&0, 1010));
+
+        // Sliding forwards by most of the window leaves 1010 just inside it.
+        assert_eq!(SequenceStatus::Accept, window.check(&0, 1137));
+        assert_eq!(SequenceStatus::Duplicate, window.check(&0, 1010));
+        assert_eq!(SequenceStatus::TooOld, window.check(&0, 1009));
+        assert_eq!(SequenceStatus::Accept, window.check(&0, 1011));
+
+        // Jumping beyond the window clears it.
+        assert_eq!(SequenceStatus::Accept, window.check(&0, 5000));
+        assert_eq!(SequenceStatus::Accept, window.check(&0, 4999));
+        assert_eq!(SequenceStatus::TooOld, window.check(&0, 1137));
+
+        // Senders are independent.
+        assert_eq!(SequenceStatus::Accept, window.check(&1, 1000));
+        assert_eq!(2, window.len());
+    }
+
+    #[test]
+    fn senders_are_bounded() {
+        let mut window = AntiReplayWindow::<u32>::with_capacity(64, 10);
+        for sender in 0..100 {
+            assert_eq!(SequenceStatus::Accept, window.check(&sender, 1));
+        }
+        assert_eq!(10, window.len());
+        assert_eq!(SequenceStatus::Duplicate, window.check(&99, 1));
+        // The oldest senders have been dropped, so start afresh.
+        assert_eq!(SequenceStatus::Accept, window.check(&0, 1));
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::f64::consts::LN_2;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use super::{Filter, FilterError, hash, rehash};
+use super::packed::PackedArray;
+
+/// Approximate message filter backed by a Bloom filter.
+///
+/// This uses a few bits per message rather than the tens of bytes needed by `MessageFilter`, in
+/// exchange for a tunable false positive rate: `contains` may report a message which was never
+/// inserted, but never fails to report one which was.  Messages can't be removed and don't expire.
+pub struct BloomMessageFilter<Message> {
+    bits: BloomBits,
+    phantom: PhantomData<Message>,
+}
+
+impl<Message: Hash> BloomMessageFilter<Message> {
+    /// Constructor for a `BloomMessageFilter` sized so that the false positive rate will be at most
+    /// `false_positive_rate` once `expected_items` messages have been inserted.
+    ///
+    /// `false_positive_rate` must be between 0 and 1 exclusive.
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> BloomMessageFilter<Message> {
+        BloomMessageFilter {
+            bits: BloomBits::new(expected_items, false_positive_rate),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Adds a message to the filter.
+    ///
+    /// Returns `true` if the message was added, or `false` if it was probably already in the
+    /// filter.
+    pub fn insert(&mut self, message: &Message) -> bool {
+        self.bits.insert(hash(message))
+    }
+
+    /// Returns whether `message` is probably in the filter or definitely isn't.
+    pub fn contains(&self, message: &Message) -> bool {
+        self.bits.contains(hash(message))
+    }
+
+    /// Returns the probability that `contains` reports a message which was never inserted, based
+    /// on the proportion of bits currently set.
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        self.bits.estimated_false_positive_rate()
+    }
+
+    /// Returns the approximate number of distinct messages added, i.e. the number of calls to
+    /// `insert` which returned `true`.
+    pub fn len(&self) -> usize {
+        self.bits.len
+    }
+
+    /// Returns whether there are no entries in the filter.
+    pub fn is_empty(&self) -> bool {
+        self.bits.len == 0
+    }
+
+    /// Returns the number of messages the filter was sized for.
+    pub fn capacity(&self) -> usize {
+        self.bits.capacity
+    }
+
+    /// Clears the filter, removing all entries.
+    pub fn clear(&mut self) {
+        self.bits.clear();
+    }
+}
+
+/// The Bloom filter proper, operating on messages' hash codes so that filters built from several
+/// of these only need to hash each message once.
+pub struct BloomBits {
+    bits: PackedArray,
+    hash_count: usize,
+    bits_set: usize,
+    /// The number of insertions which set at least one new bit.
+    pub len: usize,
+    /// The number of insertions this was sized for.
+    pub capacity: usize,
+}
+
+impl BloomBits {
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> BloomBits {
+        assert!(false_positive_rate > 0.0 && false_positive_rate < 1.0,
+                "The false positive rate must be between 0 and 1 exclusive.");
+        let items = cmp::max(expected_items, 1) as f64;
+        let bit_count = (-items * false_positive_rate.ln() / (LN_2 * LN_2)).ceil();
+        let hash_count = (bit_count / items * LN_2).round().max(1.0);
+        BloomBits {
+            bits: PackedArray::new(bit_count as usize, 1),
+            hash_count: hash_count as usize,
+            bits_set: 0,
+            len: 0,
+            capacity: expected_items,
+        }
+    }
+
+    pub fn insert(&mut self, hash_code: u64) -> bool {
+        let step = step(hash_code);
+        let mut added = false;
+        for i in 0..self.hash_count {
+            let index = bit_index(hash_code, step, i, self.bits.len());
+            if self.bits.get(index) == 0 {
+                self.bits.set(index, 1);
+                self.bits_set += 1;
+                added = true;
+            }
+        }
+        if added {
+            self.len += 1;
+        }
+        added
+    }
+
+    pub fn contains(&self, hash_code: u64) -> bool {
+        let step = step(hash_code);
+        (0..self.hash_count)
+            .all(|i| self.bits.get(bit_index(hash_code, step, i, self.bits.len())) != 0)
+    }
+
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        (self.bits_set as f64 / self.bits.len() as f64).powi(self.hash_count as i32)
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.clear();
+        self.bits_set = 0;
+        self.len = 0;
+    }
+}
+
+/// Returns the stride between successive bit indices for `hash_code`.  It's odd so that it can't
+/// share every factor with the number of bits.
+pub fn step(hash_code: u64) -> u64 {
+    rehash(hash_code, 1) | 1
+}
+
+/// Returns the `i`th of a series of indices into `len` slots for `hash_code` using double hashing.
+pub fn bit_index(hash_code: u64, step: u64, i: usize, len: usize) -> usize {
+    (hash_code.wrapping_add((i as u64).wrapping_mul(step)) % len as u64) as usize
+}
+
+
+impl<Message: Hash> Filter<Message> for BloomMessageFilter<Message> {
+    fn insert(&mut self, message: &Message) -> Result<usize, FilterError> {
+        Ok(if BloomMessageFilter::insert(self, message) { 0 } else { 1 })
+    }
+
+    fn contains(&mut self, message: &Message) -> bool {
+        BloomMessageFilter::contains(self, message)
+    }
+
+    fn len(&self) -> usize {
+        BloomMessageFilter::len(self)
+    }
+
+    fn clear(&mut self) {
+        BloomMessageFilter::clear(self)
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn false_positive_rate() {
+        let mut filter = BloomMessageFilter::<usize>::new(1000, 0.01);
+        assert!(filter.is_empty());
+        // A new message is occasionally a false positive, even while the filter is filling up.
+        let inserted = (0..1000).filter(|i| filter.insert(i)).count();
+        assert!(inserted > 990, "{} messages inserted", inserted);
+        assert_eq!(inserted, filter.len());
+
+        // There are no false negatives.
+        assert!((0..1000).all(|i| filter.contains(&i)));
+        assert!((0..1000).all(|i| !filter.insert(&i)));
+
+        // The false positive rate is close to the target.
+        let false_positives = (1000..101_000).filter(|i| filter.contains(i)).count();
+        assert!(false_positives < 2000, "{} false positives", false_positives);
+        let estimate = filter.estimated_false_positive_rate();
+        assert!(estimate > 0.005 && estimate < 0.02, "estimated rate {}", estimate);
+
+        filter.clear();
+        assert!(filter.is_empty());
+        assert_eq!(0.0, filter.estimated_false_positive_rate());
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use super::{Filter, FilterError, hash, nanos};
+
+/// Half of the bits of each slot hold the message's fingerprint, the other half its epoch.
+const EPOCH_BITS: u64 = 32;
+const EPOCH_MASK: u64 = (1 << EPOCH_BITS) - 1;
+/// The number of epochs which make up one time to live.
+const EPOCHS_PER_TIME_TO_LIVE: u64 = 16;
+
+/// A lock-free message filter which can be shared between threads.
+///
+/// Each message maps to exactly one slot of a fixed-size table.  The slot holds a fingerprint of
+/// the message along with the epoch in which it was last inserted, and is only ever changed via a
+/// single compare-and-swap.  This means all operations on a given message are linearizable, so if
+/// several threads insert the same message concurrently exactly one of them will see
+/// `insert_if_absent` return `true`.
+///
+/// Expiry is generation-based: the time to live is split into 16 epochs and an entry is treated as
+/// absent once it is more than 16 epochs old.  Entries therefore never expire early, but may live
+/// up to one sixteenth of the time to live longer than requested.
+///
+/// Unlike `MessageFilter`, the capacity is the number of slots in the table rather than a strict
+/// count of messages.  A message whose slot is already occupied by a different live message evicts
+/// that message, so the table should be sized comfortably larger than the expected number of live
+/// messages to keep such evictions rare.
+///
+/// Since only a 32-bit fingerprint of each message is stored, the filter is approximate: a new
+/// message whose slot holds a live message with the same fingerprint is wrongly reported as already
+/// seen.  The chance of this is 1 in 2<sup>32</sup> for each lookup of an occupied slot.  Epochs
+/// are likewise held in 32 bits, so a slot which is left untouched for 2<sup>28</sup> times the
+/// time to live (about 8 years at one second, or 3 days at one millisecond) briefly appears live
+/// again.
+pub struct ConcurrentFilter<Message> {
+    slots: Vec<AtomicU64>,
+    time_to_live: Option<Duration>,
+    epoch_length_nanos: u64,
+    start: Instant,
+    phantom: PhantomData<Message>,
+}
+
+impl<Message: Hash> ConcurrentFilter<Message> {
+    /// Constructor for capacity based `ConcurrentFilter`.
+    pub fn with_capacity(capacity: usize) -> ConcurrentFilter<Message> {
+        ConcurrentFilter::new(capacity, None)
+    }
+
+    /// Constructor for dual-feature capacity and time based `ConcurrentFilter`.
+    pub fn with_expiry_duration_and_capacity(time_to_live: Duration,
+                                             capacity: usize)
+                                             -> ConcurrentFilter<Message> {
+        ConcurrentFilter::new(capacity, Some(time_to_live))
+    }
+
+    /// Adds `message` to the filter if it isn't already present.
+    ///
+    /// Returns `true` if this call added the message, i.e. it was the first to see it, or `false`
+    /// if the message was already in the filter, in which case its expiry time is updated.  This
+    /// is the concurrent counterpart of `MessageFilter::insert` returning 0.
+    pub fn insert_if_absent(&self, message: &Message) -> bool {
+        let hash_code = hash(message);
+        let slot = &self.slots[self.index(hash_code)];
+        let fingerprint = fingerprint(hash_code);
+        let epoch = self.current_epoch();
+        let mut current = slot.load(Ordering::Acquire);
+        loop {
+            let present = self.is_live(current, epoch) && current >> EPOCH_BITS == fingerprint;
+            if present && current & EPOCH_MASK == epoch {
+                return false;
+            }
+            match slot.compare_exchange(current,
+                                        fingerprint << EPOCH_BITS | epoch,
+                                        Ordering::AcqRel,
+                                        Ordering::Acquire) {
+                Ok(_) => return !present,
+                Err(previous) => current = previous,
+            }
+        }
+    }
+
+    /// Returns whether `message` exists in the filter or not.
+    pub fn contains(&self, message: &Message) -> bool {
+        let hash_code = hash(message);
+        let current = self.slots[self.index(hash_code)].load(Ordering::Acquire);
+        self.is_live(current, self.current_epoch()) &&
+        current >> EPOCH_BITS == fingerprint(hash_code)
+    }
+
+    /// Removes a message from the filter.
+    pub fn remove(&self, message: &Message) {
+        let hash_code = hash(message);
+        let slot = &self.slots[self.index(hash_code)];
+        let fingerprint = fingerprint(hash_code);
+        let mut current = slot.load(Ordering::Acquire);
+        while current != 0 && current >> EPOCH_BITS == fingerprint {
+            match slot.compare_exchange(current, 0, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(previous) => current = previous,
+            }
+        }
+    }
+
+    /// Returns the number of unexpired messages in the filter.
+    ///
+    /// This walks the whole table, and concurrent modifications may or may not be reflected in the
+    /// result.
+    pub fn len(&self) -> usize {
+        let epoch = self.current_epoch();
+        self.slots.iter().filter(|slot| self.is_live(slot.load(Ordering::Acquire), epoch)).count()
+    }
+
+    /// Returns whether there are no unexpired messages in the filter.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of slots in the filter.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Clears the filter, removing all entries.
+    ///
+    /// Slots are cleared one at a time, so messages inserted concurrently may survive the call.
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.store(0, Ordering::Release);
+        }
+    }
+
+    fn new(capacity: usize, time_to_live: Option<Duration>) -> ConcurrentFilter<Message> {
+        let epoch_length_nanos = time_to_live.map_or(1, |time_to_live| {
+            ::std::cmp::max(nanos(time_to_live) / EPOCHS_PER_TIME_TO_LIVE, 1)
+        });
+        ConcurrentFilter {
+            slots: (0..::std::cmp::max(capacity, 1)).map(|_| AtomicU64::new(0)).collect(),
+            time_to_live: time_to_live,
+            epoch_length_nanos: epoch_length_nanos,
+            start: Instant::now(),
+            phantom: PhantomData,
+        }
+    }
+
+    fn index(&self, hash_code: u64) -> usize {
+        (hash_code % self.slots.len() as u64) as usize
+    }
+
+    fn current_epoch(&self) -> u64 {
+        if self.time_to_live.is_none() {
+            return 0;
+        }
+        (nanos(self.start.elapsed()) / self.epoch_length_nanos) & EPOCH_MASK
+    }
+
+    fn is_live(&self, slot_value: u64, epoch: u64) -> bool {
+        slot_value != 0 &&
+        epoch.wrapping_sub(slot_value & EPOCH_MASK) & EPOCH_MASK <= EPOCHS_PER_TIME_TO_LIVE
+    }
+}
+
+/// Takes the fingerprint from the high bits of the hash, since the low bits choose the slot.  Zero
+/// is reserved to mark an empty slot.
+fn fingerprint(hash_code: u64) -> u64 {
+    match hash_code >> EPOCH_BITS {
+        0 => 1,
+        fingerprint => fingerprint,
+    }
+}
+
+
+impl<Message: Hash> Filter<Message> for ConcurrentFilter<Message> {
+    fn insert(&mut self, message: &Message) -> Result<usize, FilterError> {
+        Ok(if self.insert_if_absent(message) { 0 } else { 1 })
+    }
+
+    fn contains(&mut self, message: &Message) -> bool {
+        ConcurrentFilter::contains(self, message)
+    }
+
+    fn remove(&mut self, message: &Message) -> Result<(), FilterError> {
+        ConcurrentFilter::remove(self, message);
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        ConcurrentFilter::len(self)
+    }
+
+    fn clear(&mut self) {
+        ConcurrentFilter::clear(self)
+    }
+
+    fn supports_remove(&self) -> bool {
+        true
+    }
+
+    fn supports_expiry(&self) -> bool {
+        self.time_to_live.is_some()
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn first_seen_wins() {
+        let filter = Arc::new(ConcurrentFilter::<usize>::with_capacity(1 << 20));
+        let threads = (0..8)
+            .map(|_| {
+                let filter = filter.clone();
+                thread::spawn(move || (0..100).filter(|i| filter.insert_if_absent(i)).count())
+            })
+            .collect::<Vec<_>>();
+        let total = threads.into_iter()
+            .map(|thread| thread.join().expect("Thread panicked."))
+            .fold(0, |total, count| total + count);
+
+        // Each message must have been reported as new exactly once across all threads.
+        assert_eq!(total, 100);
+        assert_eq!(filter.len(), 100);
+        assert!((0..100).all(|i| filter.contains(&i)));
+    }
+
+    #[test]
+    fn remove_and_clear() {
+        let filter = ConcurrentFilter::<usize>::with_capacity(1024);
+        assert!(filter.insert_if_absent(&1));
+        assert!(!filter.insert_if_absent(&1));
+        filter.remove(&1);
+        assert!(!filter.contains(&1));
+        assert!(filter.insert_if_absent(&1));
+
+        assert!(filter.insert_if_absent(&2));
+        filter.clear();
+        assert!(filter.is_empty());
+    }
+
+    #[test]
+    fn expiry() {
+        let time_to_live = Duration::from_millis(100);
+        let filter = ConcurrentFilter::<usize>::with_expiry_duration_and_capacity(time_to_live,
+                                                                                  1024);
+        assert!(filter.insert_if_absent(&0));
+        assert!(filter.contains(&0));
+
+        // Wait for half the expiry time and re-add "0", which should refresh its expiry.
+        thread::sleep(Duration::from_millis(60));
+        assert!(!filter.insert_if_absent(&0));
+        thread::sleep(Duration::from_millis(60));
+        assert!(filter.contains(&0));
+
+        // Allow the message time to expire.
+        thread::sleep(Duration::from_millis(120));
+        assert!(!filter.contains(&0));
+        assert!(filter.insert_if_absent(&0));
+    }
+}
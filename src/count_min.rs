@@ -0,0 +1,266 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::f64::consts::E;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use super::{Filter, FilterError, hash, nanos};
+use super::bloom::{bit_index, step};
+
+/// Approximate per-message counts in constant memory, backed by a count-min sketch.
+///
+/// Each message increments one counter in each of `depth` rows of `width` counters, and its count
+/// is the smallest of those.  Collisions can only inflate a count, so `count` is an upper bound on
+/// the true count.  Updates are conservative, i.e. only the counters which are
+/// currently the smallest are incremented, which reduces that inflation.
+///
+/// Optionally, counts can decay over time so that the sketch tracks recent activity: every
+/// `half_life` all counters are halved.
+pub struct CountMinSketch<Message> {
+    counters: Vec<u32>,
+    width: usize,
+    depth: usize,
+    half_life: Option<Duration>,
+    decayed_at: Instant,
+    phantom: PhantomData<Message>,
+}
+
+impl<Message: Hash> CountMinSketch<Message> {
+    /// Constructor for a `CountMinSketch` with `depth` rows of `width` counters.
+    pub fn new(width: usize, depth: usize) -> CountMinSketch<Message> {
+        let width = cmp::max(width, 1);
+        let depth = cmp::max(depth, 1);
+        CountMinSketch {
+            counters: vec![0; width * depth],
+            width: width,
+            depth: depth,
+            half_life: None,
+            decayed_at: Instant::now(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Constructor for a `CountMinSketch` whose counts overestimate by at most `epsilon` times the
+    /// total number of insertions, with probability at least `1 - delta`.
+    pub fn with_error_bounds(epsilon: f64, delta: f64) -> CountMinSketch<Message> {
+        assert!(epsilon > 0.0 && delta > 0.0 && delta < 1.0,
+                "Epsilon must be positive and delta between 0 and 1 exclusive.");
+        CountMinSketch::new((E / epsilon).ceil() as usize,
+                            (1.0 / delta).ln().ceil() as usize)
+    }
+
+    /// Makes counts decay over time, halving every `half_life`.
+    pub fn with_decay(mut self, half_life: Duration) -> CountMinSketch<Message> {
+        self.half_life = Some(half_life);
+        self.decayed_at = Instant::now();
+        self
+    }
+
+    /// Adds a message to the sketch.
+    ///
+    /// The return value is an upper bound on the number of times this message has already been
+    /// added.
+    pub fn insert(&mut self, message: &Message) -> usize {
+        self.decay();
+        let hash_code = hash(message);
+        let step = step(hash_code);
+        let count = self.estimate(hash_code, step);
+        let new_count = count.saturating_add(1);
+        for row in 0..self.depth {
+            let index = row * self.width + bit_index(hash_code, step, row, self.width);
+            self.counters[index] = cmp::max(self.counters[index], new_count);
+        }
+        count as usize
+    }
+
+    /// Returns an upper bound on the number of times this message has already been inserted, i.e.
+    /// as with `MessageFilter::count`, not counting its first insertion.
+    pub fn count(&self, message: &Message) -> usize {
+        self.insertions(message).saturating_sub(1)
+    }
+
+    /// Returns an estimate of the number of distinct messages in the sketch, based on the number
+    /// of non-zero counters in each row.
+    pub fn len(&self) -> usize {
+        self.counters
+            .chunks(self.width)
+            .map(|row| row.iter().filter(|&&counter| counter > 0).count())
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Returns whether all counts are zero.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of counters in each row.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the number of rows.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Clears the sketch, resetting all counts to zero.
+    pub fn clear(&mut self) {
+        for counter in &mut self.counters {
+            *counter = 0;
+        }
+        self.decayed_at = Instant::now();
+    }
+
+    /// Returns an upper bound on the number of times `message` has been inserted, including its
+    /// first insertion.
+    fn insertions(&self, message: &Message) -> usize {
+        let hash_code = hash(message);
+        let count = self.estimate(hash_code, step(hash_code));
+        // Apply any halvings which are due but haven't been performed yet.
+        match self.pending_halvings() {
+            halvings if halvings >= 32 => 0,
+            halvings => (count >> halvings) as usize,
+        }
+    }
+
+    fn estimate(&self, hash_code: u64, step: u64) -> u32 {
+        (0..self.depth)
+            .map(|row| {
+                self.counters[row * self.width + bit_index(hash_code, step, row, self.width)]
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn pending_halvings(&self) -> u64 {
+        match self.half_life {
+            Some(half_life) => nanos(self.decayed_at.elapsed()) / cmp::max(nanos(half_life), 1),
+            None => 0,
+        }
+    }
+
+    fn decay(&mut self) {
+        let halvings = self.pending_halvings();
+        if halvings == 0 {
+            return;
+        }
+        if halvings >= 32 {
+            // Every counter is now zero, so just start afresh.
+            for counter in &mut self.counters {
+                *counter = 0;
+            }
+            self.decayed_at = Instant::now();
+            return;
+        }
+        for counter in &mut self.counters {
+            *counter >>= halvings;
+        }
+        if let Some(half_life) = self.half_life {
+            self.decayed_at += half_life * halvings as u32;
+        }
+    }
+}
+
+
+impl<Message: Hash> Filter<Message> for CountMinSketch<Message> {
+    fn insert(&mut self, message: &Message) -> Result<usize, FilterError> {
+        Ok(CountMinSketch::insert(self, message))
+    }
+
+    fn contains(&mut self, message: &Message) -> bool {
+        self.insertions(message) > 0
+    }
+
+    fn count(&self, message: &Message) -> usize {
+        CountMinSketch::count(self, message)
+    }
+
+    fn len(&self) -> usize {
+        CountMinSketch::len(self)
+    }
+
+    fn clear(&mut self) {
+        CountMinSketch::clear(self)
+    }
+
+    fn supports_count(&self) -> bool {
+        true
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn counts_are_upper_bounds() {
+        let mut sketch = CountMinSketch::<usize>::with_error_bounds(0.01, 0.01);
+        assert_eq!(272, sketch.width());
+        assert_eq!(5, sketch.depth());
+        for i in 0..100 {
+            for _ in 0..i {
+                let _ = sketch.insert(&i);
+            }
+        }
+        // Total insertions are 4950, so each count is at most about 50 too high.
+        for i in 0..100 {
+            let count = sketch.count(&i);
+            assert!(count >= i.saturating_sub(1) && count <= i + 50,
+                    "count of {} was {}",
+                    i,
+                    count);
+        }
+        let count = sketch.insert(&99);
+        assert_eq!(count, sketch.count(&99));
+
+        sketch.clear();
+        assert_eq!(0, sketch.count(&99));
+    }
+
+    #[test]
+    fn decay() {
+        let half_life = Duration::from_millis(100);
+        let mut sketch = CountMinSketch::<usize>::new(100, 4).with_decay(half_life);
+        for _ in 0..8 {
+            let _ = sketch.insert(&0);
+        }
+        assert_eq!(7, sketch.count(&0));
+        thread::sleep(Duration::from_millis(110));
+        assert_eq!(3, sketch.count(&0));
+        assert_eq!(4, sketch.insert(&0));
+        assert_eq!(4, sketch.count(&0));
+    }
+
+    #[test]
+    fn long_idle() {
+        // Simulate being idle for more than 2^32 half lives.
+        let mut sketch = CountMinSketch::<usize>::new(100, 4).with_decay(Duration::new(0, 1));
+        sketch.decayed_at = Instant::now() - Duration::from_secs(5);
+        let _ = sketch.insert(&0);
+        assert!(sketch.decayed_at.elapsed() < Duration::from_secs(1));
+
+        sketch.half_life = Some(Duration::from_secs(60));
+        assert_eq!(1, sketch.insert(&0));
+    }
+}
@@ -0,0 +1,361 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use super::{Filter, FilterError, hash, rehash, xorshift};
+use super::packed::PackedArray;
+
+/// The number of times an insertion displaces existing fingerprints before giving up.
+const MAX_KICKS: usize = 500;
+
+/// Approximate message filter backed by a cuckoo filter.
+///
+/// Like `BloomMessageFilter` this stores a small fingerprint per message and so has a false
+/// positive rate, roughly `2 * bucket_size / 2^fingerprint_bits`, but unlike a Bloom filter it
+/// supports removing messages.  Inserting a message which is already present stores a further
+/// copy of its fingerprint, which allows `count` to report approximately how many times it was
+/// inserted.  So that one frequently repeated message can't fill its buckets and stall every other
+/// insertion, at most `bucket_size` copies are stored, so `count` saturates at `bucket_size - 1`.
+///
+/// The table holds a fixed number of fingerprints.  When an insertion can't find room for every
+/// fingerprint, the last one displaced is held aside (so no message is lost) and every further
+/// insertion fails with `FilterError::Full` until removals make room for it again.
+pub struct CuckooFilter<Message> {
+    slots: PackedArray,
+    bucket_size: usize,
+    bucket_mask: usize,
+    victim: Option<(usize, u64)>,
+    len: usize,
+    rng_state: u64,
+    phantom: PhantomData<Message>,
+}
+
+impl<Message: Hash> CuckooFilter<Message> {
+    /// Constructor for a `CuckooFilter` able to hold at least `capacity` fingerprints, using 16-bit
+    /// fingerprints in buckets of 4.
+    pub fn new(capacity: usize) -> CuckooFilter<Message> {
+        CuckooFilter::with_fingerprint_bits_and_bucket_size(capacity, 16, 4)
+    }
+
+    /// Constructor for a `CuckooFilter` able to hold at least `capacity` fingerprints, each
+    /// `fingerprint_bits` wide (between 1 and 32) in buckets of `bucket_size`.
+    pub fn with_fingerprint_bits_and_bucket_size(capacity: usize,
+                                                 fingerprint_bits: usize,
+                                                 bucket_size: usize)
+                                                 -> CuckooFilter<Message> {
+        let bucket_size = cmp::max(bucket_size, 1);
+        // The alternate bucket is found by XOR, so the number of buckets must be a power of two.
+        let bucket_count = cmp::max((capacity + bucket_size - 1) / bucket_size, 1)
+            .next_power_of_two();
+        CuckooFilter {
+            slots: PackedArray::new(bucket_count * bucket_size, fingerprint_bits),
+            bucket_size: bucket_size,
+            bucket_mask: bucket_count - 1,
+            victim: None,
+            len: 0,
+            rng_state: 0x2545_f491_4f6c_dd1d,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Adds a message to the filter.
+    ///
+    /// On success, returns the approximate number of times this message had already been added,
+    /// up to `bucket_size`; once that many copies are stored, further insertions leave the filter
+    /// unchanged.  Returns `FilterError::Full` if the filter has no room left, in which case it is
+    /// unchanged.
+    pub fn insert(&mut self, message: &Message) -> Result<usize, FilterError> {
+        if self.victim.is_some() {
+            return Err(FilterError::Full);
+        }
+        let (index, fingerprint) = self.index_and_fingerprint(message);
+        let alt_index = self.alt_index(index, fingerprint);
+        let count = self.count_in_bucket(index, fingerprint) +
+                    self.count_in_bucket(alt_index, fingerprint);
+        if count >= self.bucket_size {
+            return Ok(count);
+        }
+        self.len += 1;
+        if self.place(index, fingerprint) || self.place(alt_index, fingerprint) {
+            return Ok(count);
+        }
+
+        // Both buckets are full, so displace fingerprints until one finds an empty slot.
+        let start_index = if xorshift(&mut self.rng_state) & 1 == 0 {
+            index
+        } else {
+            alt_index
+        };
+        self.victim = self.place_with_kicks(start_index, fingerprint);
+        Ok(count)
+    }
+
+    /// Returns whether `message` is probably in the filter or definitely isn't.
+    pub fn contains(&self, message: &Message) -> bool {
+        self.copies(message) > 0
+    }
+
+    /// Returns the approximate number of times this message has already been inserted, i.e. as
+    /// with `MessageFilter::count`, not counting its first insertion.
+    pub fn count(&self, message: &Message) -> usize {
+        self.copies(message).saturating_sub(1)
+    }
+
+    /// Removes one copy of a message from the filter.
+    ///
+    /// Returns `false` if the message wasn't found.  Only remove messages which were definitely
+    /// inserted, otherwise the fingerprint of a different message may be removed instead.
+    pub fn remove(&mut self, message: &Message) -> bool {
+        let (index, fingerprint) = self.index_and_fingerprint(message);
+        let alt_index = self.alt_index(index, fingerprint);
+        if let Some((victim_index, victim_fingerprint)) = self.victim {
+            if victim_fingerprint == fingerprint &&
+               (victim_index == index || victim_index == alt_index) {
+                self.victim = None;
+                self.len -= 1;
+                return true;
+            }
+        }
+        if !self.take(index, fingerprint) && !self.take(alt_index, fingerprint) {
+            return false;
+        }
+        self.len -= 1;
+        // There may now be room to put back the fingerprint held aside.
+        if let Some((victim_index, victim_fingerprint)) = self.victim.take() {
+            self.victim = self.place_with_kicks(victim_index, victim_fingerprint);
+        }
+        true
+    }
+
+    /// Returns the number of fingerprints in the filter.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether there are no entries in the filter.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of fingerprints the table can hold.  In practice inserts start to fail
+    /// at around 95% of this for buckets of 4.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns whether an entry is being held aside, i.e. the filter is full.
+    pub fn is_full(&self) -> bool {
+        self.victim.is_some()
+    }
+
+    /// Clears the filter, removing all entries.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.victim = None;
+        self.len = 0;
+    }
+
+    fn index_and_fingerprint(&self, message: &Message) -> (usize, u64) {
+        let hash_code = hash(message);
+        let fingerprint = match rehash(hash_code, 2) & self.slots.max_value() {
+            // Zero marks an empty slot.
+            0 => 1,
+            fingerprint => fingerprint,
+        };
+        (hash_code as usize & self.bucket_mask, fingerprint)
+    }
+
+    fn alt_index(&self, index: usize, fingerprint: u64) -> usize {
+        (index ^ rehash(fingerprint, 3) as usize) & self.bucket_mask
+    }
+
+    /// Returns the number of copies of `message`'s fingerprint held, including any held aside.
+    fn copies(&self, message: &Message) -> usize {
+        let (index, fingerprint) = self.index_and_fingerprint(message);
+        let alt_index = self.alt_index(index, fingerprint);
+        let in_victim = match self.victim {
+            Some((victim_index, victim_fingerprint)) => {
+                victim_fingerprint == fingerprint &&
+                (victim_index == index || victim_index == alt_index)
+            }
+            None => false,
+        };
+        self.count_in_bucket(index, fingerprint) + self.count_in_bucket(alt_index, fingerprint) +
+        if in_victim { 1 } else { 0 }
+    }
+
+    fn count_in_bucket(&self, index: usize, fingerprint: u64) -> usize {
+        let start = index * self.bucket_size;
+        (start..start + self.bucket_size)
+            .filter(|&slot| self.slots.get(slot) == fingerprint)
+            .count()
+    }
+
+    /// Puts `fingerprint` into an empty slot of the bucket, returning whether there was one.
+    fn place(&mut self, index: usize, fingerprint: u64) -> bool {
+        let start = index * self.bucket_size;
+        match (start..start + self.bucket_size).find(|&slot| self.slots.get(slot) == 0) {
+            Some(slot) => {
+                self.slots.set(slot, fingerprint);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Puts `fingerprint` into the bucket at `index`, displacing existing fingerprints to their
+    /// alternate buckets as required.  Returns the fingerprint left without a slot, if any.
+    fn place_with_kicks(&mut self, index: usize, fingerprint: u64) -> Option<(usize, u64)> {
+        let mut index = index;
+        let mut fingerprint = fingerprint;
+        if self.place(index, fingerprint) {
+            return None;
+        }
+        for _ in 0..MAX_KICKS {
+            let slot = index * self.bucket_size +
+                       (xorshift(&mut self.rng_state) % self.bucket_size as u64) as usize;
+            let displaced = self.slots.get(slot);
+            self.slots.set(slot, fingerprint);
+            fingerprint = displaced;
+            index = self.alt_index(index, fingerprint);
+            if self.place(index, fingerprint) {
+                return None;
+            }
+        }
+        Some((index, fingerprint))
+    }
+
+    /// Empties a slot of the bucket holding `fingerprint`, returning whether there was one.
+    fn take(&mut self, index: usize, fingerprint: u64) -> bool {
+        let start = index * self.bucket_size;
+        match (start..start + self.bucket_size).find(|&slot| self.slots.get(slot) == fingerprint) {
+            Some(slot) => {
+                self.slots.set(slot, 0);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+
+impl<Message: Hash> Filter<Message> for CuckooFilter<Message> {
+    fn insert(&mut self, message: &Message) -> Result<usize, FilterError> {
+        CuckooFilter::insert(self, message)
+    }
+
+    fn contains(&mut self, message: &Message) -> bool {
+        CuckooFilter::contains(self, message)
+    }
+
+    fn count(&self, message: &Message) -> usize {
+        CuckooFilter::count(self, message)
+    }
+
+    fn remove(&mut self, message: &Message) -> Result<(), FilterError> {
+        let _ = CuckooFilter::remove(self, message);
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        CuckooFilter::len(self)
+    }
+
+    fn clear(&mut self) {
+        CuckooFilter::clear(self)
+    }
+
+    fn supports_remove(&self) -> bool {
+        true
+    }
+
+    fn supports_count(&self) -> bool {
+        true
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use FilterError;
+
+    #[test]
+    fn insert_count_remove() {
+        let mut filter = CuckooFilter::<usize>::new(1000);
+        for i in 0..500 {
+            assert_eq!(Ok(0), filter.insert(&i));
+        }
+        assert_eq!(500, filter.len());
+        assert!((0..500).all(|i| filter.contains(&i)));
+
+        assert_eq!(Ok(1), filter.insert(&0));
+        assert_eq!(1, filter.count(&0));
+        assert!(filter.remove(&0));
+        assert_eq!(0, filter.count(&0));
+        assert!(filter.contains(&0));
+        assert!(filter.remove(&0));
+        assert!(!filter.contains(&0));
+        assert!(!filter.remove(&0));
+        assert_eq!(499, filter.len());
+
+        filter.clear();
+        assert!(filter.is_empty());
+    }
+
+    #[test]
+    fn repeated_message() {
+        // A single message inserted many times mustn't fill its buckets and stall the filter.
+        let mut filter = CuckooFilter::<usize>::new(1000);
+        for i in 0..4 {
+            assert_eq!(Ok(i), filter.insert(&0));
+        }
+        for _ in 0..100 {
+            assert_eq!(Ok(4), filter.insert(&0));
+        }
+        assert!(!filter.is_full());
+        assert_eq!(3, filter.count(&0));
+        assert_eq!(4, filter.len());
+        assert_eq!(Ok(0), filter.insert(&1));
+        assert!(filter.contains(&1));
+    }
+
+    #[test]
+    fn full() {
+        let mut filter = CuckooFilter::<usize>::with_fingerprint_bits_and_bucket_size(64, 12, 2);
+        let mut inserted = 0;
+        while filter.insert(&inserted).is_ok() {
+            inserted += 1;
+        }
+        assert!(filter.is_full());
+        assert_eq!(Err(FilterError::Full), filter.insert(&inserted));
+        // Nothing which was successfully inserted has been lost.
+        assert_eq!(inserted, filter.len());
+        assert!((0..inserted).all(|i| filter.contains(&i)));
+
+        // Removing entries makes room again.
+        for i in 0..inserted / 2 {
+            assert!(filter.remove(&i));
+        }
+        assert!(!filter.is_full());
+        assert!((inserted / 2..inserted).all(|i| filter.contains(&i)));
+        assert!(filter.insert(&inserted).is_ok());
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::time::Instant;
+use super::MessageFilter;
+
+/// A view into a single message's entry in a `MessageFilter`, returned by `MessageFilter::entry`.
+///
+/// This allows conditional logic such as "insert unless already seen three times" to be done
+/// with a single lookup.
+pub enum FilterEntry<'a, Message: 'a> {
+    /// The message is in the filter.
+    Occupied(OccupiedFilterEntry<'a, Message>),
+    /// The message is reserved via `MessageFilter::reserve`, so behaves as though it's in the
+    /// filter, but won't be added until its lease is committed.
+    Reserved(ReservedFilterEntry<'a, Message>),
+    /// The message isn't in the filter.
+    Vacant(VacantFilterEntry<'a, Message>),
+}
+
+impl<'a, Message: 'a> FilterEntry<'a, Message> {
+    /// Returns the number of times this message has already been inserted.
+    pub fn count(&self) -> usize {
+        match *self {
+            FilterEntry::Occupied(ref entry) => entry.count(),
+            FilterEntry::Reserved(ref entry) => entry.count(),
+            FilterEntry::Vacant(_) => 0,
+        }
+    }
+
+    /// Inserts the message, as with `MessageFilter::insert`.
+    pub fn insert(self) -> usize {
+        match self {
+            FilterEntry::Occupied(entry) => entry.insert(),
+            FilterEntry::Reserved(entry) => entry.insert(),
+            FilterEntry::Vacant(entry) => entry.insert(),
+        }
+    }
+}
+
+/// An entry of a `MessageFilter` whose message is present.
+pub struct OccupiedFilterEntry<'a, Message: 'a> {
+    filter: &'a mut MessageFilter<Message>,
+    index: usize,
+}
+
+impl<'a, Message: 'a> OccupiedFilterEntry<'a, Message> {
+    /// Returns the number of times this message has already been inserted.
+    pub fn count(&self) -> usize {
+        self.filter.entries.value(self.index).count
+    }
+
+    /// Returns when the message will expire, or `None` if the filter isn't time based.
+    pub fn expiry(&self) -> Option<Instant> {
+        self.filter.entries.expiry(self.index)
+    }
+
+    /// Updates the message's expiry time and moves it to the back of the FIFO queue, as `insert`
+    /// does, but without counting another copy of it.
+    pub fn refresh(&mut self) {
+        self.index = self.filter.entries.refresh(self.index);
+    }
+
+    /// Inserts another copy of the message, as with `MessageFilter::insert`.
+    pub fn insert(self) -> usize {
+        self.filter.insert_at(self.index)
+    }
+
+    /// Removes the message from the filter.
+    pub fn remove(self) {
+        let _ = self.filter.entries.remove_at(self.index);
+    }
+}
+
+/// An entry of a `MessageFilter` whose message is reserved.
+pub struct ReservedFilterEntry<'a, Message: 'a> {
+    filter: &'a mut MessageFilter<Message>,
+    hash_code: u64,
+}
+
+impl<'a, Message: 'a> ReservedFilterEntry<'a, Message> {
+    /// Returns the number of copies of this message seen before the reserved one, as with
+    /// `MessageFilter::count`.
+    pub fn count(&self) -> usize {
+        self.filter.count_hash_code(self.hash_code)
+    }
+
+    /// Inserts another copy of the message, as with `MessageFilter::insert`.
+    pub fn insert(self) -> usize {
+        self.filter.insert_unless_reserved(self.hash_code)
+    }
+}
+
+/// An entry of a `MessageFilter` whose message is absent.
+pub struct VacantFilterEntry<'a, Message: 'a> {
+    filter: &'a mut MessageFilter<Message>,
+    hash_code: u64,
+}
+
+impl<'a, Message: 'a> VacantFilterEntry<'a, Message> {
+    /// Inserts the message, as with `MessageFilter::insert`.  The return value is always 0.
+    pub fn insert(self) -> usize {
+        self.filter.insert_new(self.hash_code)
+    }
+}
+
+impl<Message> MessageFilter<Message> {
+    /// Removes any expired messages, then returns the entry for `message` for in-place
+    /// manipulation.
+    pub fn entry<'a>(&'a mut self, message: &Message) -> FilterEntry<'a, Message> {
+        self.apply_leases();
+        self.remove_expired();
+        let hash_code = (self.hash_fn)(message);
+        let position = self.entries.position(hash_code);
+        let reserved = self.leases.as_ref().map_or(false, |leases| leases.is_held(hash_code));
+        match position {
+            Some(index) => {
+                FilterEntry::Occupied(OccupiedFilterEntry {
+                    filter: self,
+                    index: index,
+                })
+            }
+            None if reserved => {
+                FilterEntry::Reserved(ReservedFilterEntry {
+                    filter: self,
+                    hash_code: hash_code,
+                })
+            }
+            None => {
+                FilterEntry::Vacant(VacantFilterEntry {
+                    filter: self,
+                    hash_code: hash_code,
+                })
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use MessageFilter;
+
+    #[test]
+    fn conditional_insert() {
+        let mut filter = MessageFilter::<usize>::with_capacity(10);
+        for _ in 0..10 {
+            let entry = filter.entry(&0);
+            if entry.count() < 3 {
+                let _ = entry.insert();
+            }
+        }
+        assert_eq!(3, filter.count(&0));
+
+        match filter.entry(&0) {
+            FilterEntry::Occupied(entry) => {
+                assert_eq!(None, entry.expiry());
+                entry.remove();
+            }
+            _ => panic!("Entry should be occupied."),
+        }
+        match filter.entry(&0) {
+            FilterEntry::Vacant(entry) => assert_eq!(0, entry.insert()),
+            _ => panic!("Entry should be vacant."),
+        }
+        assert_eq!(1, filter.len());
+    }
+
+    #[test]
+    fn refresh() {
+        let time_to_live = Duration::from_millis(100);
+        let mut filter = MessageFilter::<usize>::with_expiry_duration(time_to_live);
+        let _ = filter.insert(&0);
+        let _ = filter.insert(&1);
+        thread::sleep(Duration::from_millis(60));
+        match filter.entry(&0) {
+            FilterEntry::Occupied(mut entry) => {
+                entry.refresh();
+                assert_eq!(0, entry.count());
+                assert!(entry.expiry().expect("Filter should be time based.") >
+                        Instant::now() + Duration::from_millis(90));
+            }
+            _ => panic!("Entry should be occupied."),
+        }
+        thread::sleep(Duration::from_millis(60));
+        assert!(filter.contains(&0));
+        assert!(!filter.contains(&1));
+    }
+
+    #[test]
+    fn reserved() {
+        let mut filter = MessageFilter::<usize>::with_capacity(10);
+        let lease = filter.reserve(&0, Duration::from_secs(60)).expect("Failed to reserve.");
+        match filter.entry(&0) {
+            FilterEntry::Reserved(entry) => {
+                assert_eq!(0, entry.count());
+                assert_eq!(1, entry.insert());
+            }
+            _ => panic!("Entry should be reserved."),
+        }
+        assert_eq!(1, filter.entry(&0).count());
+        assert_eq!(0, filter.len());
+
+        // Committing the lease adds the message with the copy seen while it was reserved.
+        assert!(lease.commit());
+        match filter.entry(&0) {
+            FilterEntry::Occupied(entry) => assert_eq!(1, entry.count()),
+            _ => panic!("Entry should be occupied."),
+        }
+        assert_eq!(1, filter.count(&0));
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Errors returned by the filters in this crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterError {
+    /// The filter has no room left for the message.
+    Full,
+    /// Serialised data couldn't be parsed.
+    InvalidEncoding,
+    /// Filters which are combined don't identify messages in the same way.
+    KeyMismatch,
+    /// The operation isn't supported by this kind of filter.
+    Unsupported,
+}
+
+impl Display for FilterError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let description = match *self {
+            FilterError::Full => "Filter is full",
+            FilterError::InvalidEncoding => "Invalid serialised filter",
+            FilterError::KeyMismatch => "Filters identify messages differently",
+            FilterError::Unsupported => "Operation not supported by this filter",
+        };
+        write!(formatter, "{}", description)
+    }
+}
+
+impl Error for FilterError {}
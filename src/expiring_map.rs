@@ -0,0 +1,304 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::hash::Hash;
+use std::mem;
+use std::time::Duration;
+use super::hash;
+use super::timed_store::TimedStore;
+
+/// Map whose entries are bounded by capacity and/or time to live in the same way as the messages
+/// in a `MessageFilter`, i.e. an expiring cache.
+///
+/// Inserting a key which is already present updates its expiry time and moves it to the back of
+/// the FIFO queue again; reading or modifying a value in place doesn't.
+///
+/// Unlike `MessageFilter`, the keys themselves are stored, so a hash collision can't return
+/// another key's value.  Also unlike `MessageFilter`, a capacity of zero is treated as one, since
+/// `entry` must be able to hold the value it inserts.
+pub struct ExpiringMap<Key, Value> {
+    entries: TimedStore<(Key, Value)>,
+}
+
+impl<Key: Hash + Eq, Value> ExpiringMap<Key, Value> {
+    /// Constructor for capacity based `ExpiringMap`.
+    pub fn with_capacity(capacity: usize) -> ExpiringMap<Key, Value> {
+        ExpiringMap { entries: TimedStore::new(Some(cmp::max(capacity, 1)), None) }
+    }
+
+    /// Constructor for time based `ExpiringMap`.
+    pub fn with_expiry_duration(time_to_live: Duration) -> ExpiringMap<Key, Value> {
+        ExpiringMap { entries: TimedStore::new(None, Some(time_to_live)) }
+    }
+
+    /// Constructor for dual-feature capacity and time based `ExpiringMap`.
+    pub fn with_expiry_duration_and_capacity(time_to_live: Duration,
+                                             capacity: usize)
+                                             -> ExpiringMap<Key, Value> {
+        ExpiringMap { entries: TimedStore::new(Some(cmp::max(capacity, 1)), Some(time_to_live)) }
+    }
+
+    /// Returns the value for `key`, unless it has expired.
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        match self.entries.get(hash(key)) {
+            Some(&(ref entry_key, ref value)) if entry_key == key => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Removes any expired entries, then returns the value for `key`.
+    pub fn get_mut(&mut self, key: &Key) -> Option<&mut Value> {
+        match self.entries.get_mut(hash(key)) {
+            Some(&mut (ref entry_key, ref mut value)) if entry_key == key => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns whether `key` is in the map and hasn't expired.
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Removes any expired entries, then sets the value for `key`, returning the previous value if
+    /// any.
+    ///
+    /// If this takes the map over capacity, the oldest entry is removed.
+    pub fn insert(&mut self, key: Key, value: Value) -> Option<Value> {
+        let hash_code = hash(&key);
+        // A different key with the same hash code is simply displaced.
+        match self.entries.insert(hash_code, (key, value)) {
+            (Some((old_key, old_value)), Some(index)) if self.entries.value(index).0 == old_key => {
+                Some(old_value)
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes any expired entries, then removes and returns the value for `key`.
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        self.entries.remove_expired();
+        let index = match self.entries.position(hash(key)) {
+            Some(index) if self.entries.value(index).0 == *key => index,
+            _ => return None,
+        };
+        Some(self.entries.remove_at(index).1)
+    }
+
+    /// Removes any expired entries, then returns the entry for `key` for in-place manipulation.
+    pub fn entry<'a>(&'a mut self, key: Key) -> Entry<'a, Key, Value> {
+        self.entries.remove_expired();
+        let hash_code = hash(&key);
+        match self.entries.position(hash_code) {
+            Some(index) if self.entries.value(index).0 == key => {
+                Entry::Occupied(OccupiedEntry {
+                    entries: &mut self.entries,
+                    index: index,
+                })
+            }
+            _ => {
+                Entry::Vacant(VacantEntry {
+                    entries: &mut self.entries,
+                    hash_code: hash_code,
+                    key: key,
+                })
+            }
+        }
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&mut self) -> usize {
+        self.entries.remove_expired();
+        self.entries.len()
+    }
+
+    /// Returns whether there are no entries in the map.
+    pub fn is_empty(&mut self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clears the map, removing all entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Removes any expired entries.
+    pub fn remove_expired(&mut self) {
+        self.entries.remove_expired();
+    }
+}
+
+/// A view into a single entry of an `ExpiringMap`, returned by `ExpiringMap::entry`.
+pub enum Entry<'a, Key: 'a, Value: 'a> {
+    /// The key is in the map.
+    Occupied(OccupiedEntry<'a, Key, Value>),
+    /// The key isn't in the map.
+    Vacant(VacantEntry<'a, Key, Value>),
+}
+
+impl<'a, Key: 'a, Value: 'a> Entry<'a, Key, Value> {
+    /// Inserts `default` if the entry is vacant, then returns the value.
+    pub fn or_insert(self, default: Value) -> &'a mut Value {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Inserts the result of `default` if the entry is vacant, then returns the value.
+    pub fn or_insert_with<F: FnOnce() -> Value>(self, default: F) -> &'a mut Value {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Returns the entry's key.
+    pub fn key(&self) -> &Key {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+}
+
+/// An entry of an `ExpiringMap` whose key is present.
+pub struct OccupiedEntry<'a, Key: 'a, Value: 'a> {
+    entries: &'a mut TimedStore<(Key, Value)>,
+    index: usize,
+}
+
+impl<'a, Key: 'a, Value: 'a> OccupiedEntry<'a, Key, Value> {
+    /// Returns the entry's key.
+    pub fn key(&self) -> &Key {
+        &self.entries.value(self.index).0
+    }
+
+    /// Returns the entry's value.
+    pub fn get(&self) -> &Value {
+        &self.entries.value(self.index).1
+    }
+
+    /// Returns the entry's value mutably.
+    pub fn get_mut(&mut self) -> &mut Value {
+        &mut self.entries.value_mut(self.index).1
+    }
+
+    /// Returns the entry's value mutably, for the lifetime of the borrow of the map.
+    pub fn into_mut(self) -> &'a mut Value {
+        let entries = self.entries;
+        &mut entries.value_mut(self.index).1
+    }
+
+    /// Replaces the entry's value, returning the old one.  The expiry time isn't updated.
+    pub fn insert(&mut self, value: Value) -> Value {
+        mem::replace(self.get_mut(), value)
+    }
+
+    /// Removes the entry from the map, returning its value.
+    pub fn remove(self) -> Value {
+        self.entries.remove_at(self.index).1
+    }
+}
+
+/// An entry of an `ExpiringMap` whose key is absent.
+pub struct VacantEntry<'a, Key: 'a, Value: 'a> {
+    entries: &'a mut TimedStore<(Key, Value)>,
+    hash_code: u64,
+    key: Key,
+}
+
+impl<'a, Key: 'a, Value: 'a> VacantEntry<'a, Key, Value> {
+    /// Returns the key which would be inserted.
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+
+    /// Inserts `value`, as with `ExpiringMap::insert`, and returns it.
+    pub fn insert(self, value: Value) -> &'a mut Value {
+        let entries = self.entries;
+        match entries.insert(self.hash_code, (self.key, value)) {
+            (_, Some(index)) => &mut entries.value_mut(index).1,
+            // The capacity is never zero, so the new entry is always kept.
+            (_, None) => unreachable!(),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn capacity_and_expiry() {
+        let time_to_live = Duration::from_millis(100);
+        let mut map = ExpiringMap::with_expiry_duration_and_capacity(time_to_live, 2);
+        assert_eq!(None, map.insert(1, "a"));
+        assert_eq!(None, map.insert(2, "b"));
+        assert_eq!(Some("a"), map.insert(1, "c"));
+        assert_eq!(None, map.insert(3, "d"));
+        assert!(!map.contains_key(&2));
+        assert_eq!(Some(&"c"), map.get(&1));
+        if let Some(value) = map.get_mut(&3) {
+            *value = "e";
+        }
+        assert_eq!(Some("e"), map.remove(&3));
+        assert_eq!(None, map.remove(&3));
+        assert_eq!(1, map.len());
+
+        thread::sleep(Duration::from_millis(110));
+        assert_eq!(None, map.get(&1));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn entry() {
+        let mut map = ExpiringMap::<&str, usize>::with_capacity(10);
+        *map.entry("a").or_insert(0) += 1;
+        *map.entry("a").or_insert(0) += 1;
+        *map.entry("b").or_insert_with(|| 10) += 1;
+        assert_eq!(Some(&2), map.get(&"a"));
+        assert_eq!(Some(&11), map.get(&"b"));
+
+        match map.entry("a") {
+            Entry::Occupied(mut entry) => {
+                assert_eq!("a", *entry.key());
+                assert_eq!(2, entry.insert(5));
+                assert_eq!(5, entry.remove());
+            }
+            Entry::Vacant(_) => panic!("Entry should be occupied."),
+        }
+        match map.entry("a") {
+            Entry::Occupied(_) => panic!("Entry should be vacant."),
+            Entry::Vacant(entry) => assert_eq!(3, *entry.insert(3)),
+        }
+        assert_eq!(2, map.len());
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut map = ExpiringMap::<&str, usize>::with_capacity(0);
+        assert_eq!(None, map.insert("a", 0));
+        assert_eq!(1, *map.entry("b").or_insert(1));
+        assert!(!map.contains_key(&"a"));
+        assert_eq!(1, map.len());
+    }
+}
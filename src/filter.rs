@@ -0,0 +1,182 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use super::{FilterError, MessageFilter};
+
+/// Operations common to all the filters in this crate, allowing the backend to be chosen at
+/// runtime, e.g. as a `Box<Filter<Message>>`.
+///
+/// Not every backend can support every operation, so the capability queries report what a given
+/// filter offers.  The exception is `StaticFilter`, which is immutable and so doesn't implement
+/// this trait; see `LayeredFilter` for combining one with a `MessageFilter`.
+pub trait Filter<Message> {
+    /// Adds a message to the filter.
+    ///
+    /// As with `MessageFilter::insert`, the return value is the number of times this message has
+    /// already been added, which is then its `count`.  Backends which don't count messages (see
+    /// `supports_count`) return 1 if the message was already present and 0 otherwise.
+    fn insert(&mut self, message: &Message) -> Result<usize, FilterError>;
+
+    /// Returns whether `message` exists in the filter or not.  For approximate backends this may be
+    /// a false positive.
+    fn contains(&mut self, message: &Message) -> bool;
+
+    /// Returns the number of times this message has already been inserted, as with
+    /// `MessageFilter::count`, i.e. not counting its first insertion.  Backends which don't count
+    /// messages can't tell how many times a message was inserted, so always return 0.
+    fn count(&self, _message: &Message) -> usize {
+        0
+    }
+
+    /// Removes a message from the filter.
+    ///
+    /// Returns `FilterError::Unsupported` if the backend can't remove messages.
+    fn remove(&mut self, _message: &Message) -> Result<(), FilterError> {
+        Err(FilterError::Unsupported)
+    }
+
+    /// Returns the number of messages in the filter.  Approximate backends may only be able to
+    /// estimate this.
+    fn len(&self) -> usize;
+
+    /// Returns whether there are no entries in the filter.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clears the filter, removing all entries.
+    fn clear(&mut self);
+
+    /// Returns whether `remove` is supported.
+    fn supports_remove(&self) -> bool {
+        false
+    }
+
+    /// Returns whether `insert` and `count` report how many times a message was inserted, rather
+    /// than just whether it was present.
+    fn supports_count(&self) -> bool {
+        false
+    }
+
+    /// Returns whether entries expire after a time to live.
+    fn supports_expiry(&self) -> bool {
+        false
+    }
+
+    /// Returns whether the filter is free of false positives.
+    fn is_exact(&self) -> bool {
+        false
+    }
+}
+
+impl<Message> Filter<Message> for MessageFilter<Message> {
+    fn insert(&mut self, message: &Message) -> Result<usize, FilterError> {
+        Ok(MessageFilter::insert(self, message))
+    }
+
+    fn contains(&mut self, message: &Message) -> bool {
+        MessageFilter::contains(self, message)
+    }
+
+    fn count(&self, message: &Message) -> usize {
+        MessageFilter::count(self, message)
+    }
+
+    fn remove(&mut self, message: &Message) -> Result<(), FilterError> {
+        MessageFilter::remove(self, message);
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        MessageFilter::len(self)
+    }
+
+    fn clear(&mut self) {
+        MessageFilter::clear(self)
+    }
+
+    fn supports_remove(&self) -> bool {
+        true
+    }
+
+    fn supports_count(&self) -> bool {
+        true
+    }
+
+    fn supports_expiry(&self) -> bool {
+        self.entries.time_to_live().is_some()
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use {BloomMessageFilter, ConcurrentFilter, CountMinSketch, CuckooFilter, FilterError,
+         LayeredFilter, MessageFilter, RotatingBloomFilter, ScalableBloomFilter, StableBloomFilter,
+         StaticFilter};
+    use std::time::Duration;
+
+    #[test]
+    fn backends_are_interchangeable() {
+        let time_to_live = Duration::from_secs(60);
+        check_backend(MessageFilter::with_capacity(100));
+        check_backend(ConcurrentFilter::with_capacity(1024));
+        check_backend(BloomMessageFilter::new(100, 0.001));
+        check_backend(RotatingBloomFilter::new(time_to_live, 4, 100, 0.001));
+        check_backend(CuckooFilter::new(100));
+        check_backend(CountMinSketch::new(1000, 4));
+        check_backend(StableBloomFilter::new(10_000, 3, 0.001));
+        check_backend(ScalableBloomFilter::new(10, 0.001));
+        check_backend(LayeredFilter::new(StaticFilter::new(&[100, 101]),
+                                         MessageFilter::with_capacity(100))
+            .expect("Filters should match."));
+    }
+
+    fn check_backend<F: Filter<usize>>(mut filter: F) {
+        assert_eq!(Ok(0), filter.insert(&1));
+        assert_eq!(1, filter.len());
+        assert_eq!(0, filter.count(&1));
+        assert_eq!(Ok(1), filter.insert(&1));
+        assert!(filter.contains(&1));
+        assert!(!filter.contains(&2));
+        assert_eq!(0, filter.count(&2));
+        let expected_count = if filter.supports_count() { 1 } else { 0 };
+        assert_eq!(expected_count, filter.count(&1));
+
+        assert_eq!(Ok(0), filter.insert(&2));
+        match filter.remove(&2) {
+            Ok(()) => {
+                assert!(filter.supports_remove());
+                assert!(!filter.contains(&2));
+            }
+            Err(error) => {
+                assert!(!filter.supports_remove());
+                assert_eq!(FilterError::Unsupported, error);
+            }
+        }
+
+        filter.clear();
+        assert!(filter.is_empty());
+        assert!(!filter.contains(&1));
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::Duration;
+use super::hash;
+use super::timed_store::TimedStore;
+
+/// Message filter for gossip protocols which also records which peers each message was received
+/// from, so that it isn't forwarded back to them.
+///
+/// Messages are bounded by capacity and/or time to live in the same way as in a `MessageFilter`.
+/// Up to `max_peers` sources are recorded per message; beyond that the earliest are forgotten, so
+/// the message may be forwarded back to them.
+pub struct GossipFilter<Message, Peer> {
+    entries: TimedStore<GossipEntry>,
+    max_peers: usize,
+    phantom: PhantomData<(Message, Peer)>,
+}
+
+struct GossipEntry {
+    /// How many copies of this message have been received before the latest one.
+    count: usize,
+    /// Hash codes of the peers this message was received from, from earliest to latest.
+    peers: Vec<u64>,
+}
+
+impl<Message: Hash, Peer: Hash> GossipFilter<Message, Peer> {
+    /// Constructor for capacity based `GossipFilter` recording up to `max_peers` sources per
+    /// message.
+    pub fn with_capacity(capacity: usize, max_peers: usize) -> GossipFilter<Message, Peer> {
+        GossipFilter::new(Some(capacity), None, max_peers)
+    }
+
+    /// Constructor for time based `GossipFilter` recording up to `max_peers` sources per message.
+    pub fn with_expiry_duration(time_to_live: Duration,
+                                max_peers: usize)
+                                -> GossipFilter<Message, Peer> {
+        GossipFilter::new(None, Some(time_to_live), max_peers)
+    }
+
+    /// Constructor for dual-feature capacity and time based `GossipFilter` recording up to
+    /// `max_peers` sources per message.
+    pub fn with_expiry_duration_and_capacity(time_to_live: Duration,
+                                             capacity: usize,
+                                             max_peers: usize)
+                                             -> GossipFilter<Message, Peer> {
+        GossipFilter::new(Some(capacity), Some(time_to_live), max_peers)
+    }
+
+    /// Records that `message` was received from `source`.
+    ///
+    /// As with `MessageFilter::insert`, the message's expiry time is updated and it is moved to
+    /// the back of the FIFO queue, and the return value is the number of times this message has
+    /// already been received (from any peer).
+    pub fn insert(&mut self, message: &Message, source: &Peer) -> usize {
+        let hash_code = hash(message);
+        let peer = hash(source);
+        let mut entry = match self.entries.remove(hash_code) {
+            Some(mut entry) => {
+                entry.count += 1;
+                entry
+            }
+            None => {
+                GossipEntry {
+                    count: 0,
+                    peers: vec![],
+                }
+            }
+        };
+        if !entry.peers.contains(&peer) {
+            if entry.peers.len() >= self.max_peers {
+                let _ = entry.peers.remove(0);
+            }
+            entry.peers.push(peer);
+        }
+        let count = entry.count;
+        let _ = self.entries.insert(hash_code, entry);
+        count
+    }
+
+    /// Returns whether `message` should be forwarded to `peer`, i.e. it hasn't been received from
+    /// that peer.
+    pub fn should_forward(&self, message: &Message, peer: &Peer) -> bool {
+        let peer = hash(peer);
+        self.entries.get(hash(message)).map_or(true, |entry| !entry.peers.contains(&peer))
+    }
+
+    /// Returns whether `message` has been received from any peer.
+    pub fn contains(&self, message: &Message) -> bool {
+        self.entries.get(hash(message)).is_some()
+    }
+
+    /// Returns the number of messages in the filter.
+    pub fn len(&mut self) -> usize {
+        self.entries.remove_expired();
+        self.entries.len()
+    }
+
+    /// Returns whether there are no entries in the filter.
+    pub fn is_empty(&mut self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clears the filter, removing all entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn new(capacity: Option<usize>,
+           time_to_live: Option<Duration>,
+           max_peers: usize)
+           -> GossipFilter<Message, Peer> {
+        GossipFilter {
+            entries: TimedStore::new(capacity, time_to_live),
+            max_peers: cmp::max(max_peers, 1),
+            phantom: PhantomData,
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn forward_to_other_peers() {
+        let mut filter = GossipFilter::<u64, &str>::with_capacity(10, 2);
+        assert!(filter.should_forward(&0, &"a"));
+        assert_eq!(0, filter.insert(&0, &"a"));
+        assert_eq!(1, filter.insert(&0, &"b"));
+        assert_eq!(2, filter.insert(&0, &"b"));
+        assert!(filter.contains(&0));
+        assert!(!filter.should_forward(&0, &"a"));
+        assert!(!filter.should_forward(&0, &"b"));
+        assert!(filter.should_forward(&0, &"c"));
+        assert!(filter.should_forward(&1, &"a"));
+
+        // Only the two latest sources are remembered.
+        assert_eq!(3, filter.insert(&0, &"c"));
+        assert!(filter.should_forward(&0, &"a"));
+        assert!(!filter.should_forward(&0, &"c"));
+
+        for message in 1..11 {
+            let _ = filter.insert(&message, &"a");
+        }
+        assert_eq!(10, filter.len());
+        assert!(!filter.contains(&0));
+    }
+}
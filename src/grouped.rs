@@ -0,0 +1,284 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::Duration;
+use super::hash;
+use super::timed_store::TimedStore;
+
+/// Message filter whose entries are divided into groups, e.g. per session or channel, which can
+/// each be removed as a whole.
+///
+/// Each group is a separate namespace, so the same message can be added to several groups
+/// independently.  Otherwise messages are bounded by capacity and/or time to live across all
+/// groups in the same way as in a `MessageFilter`.
+///
+/// `remove_group` takes constant time: the group's entries are marked as stale rather than
+/// searched for, and are only dropped once they reach the front of the queue or stale entries
+/// make up half of the filter.
+pub struct GroupedFilter<Message, Group> {
+    /// The entries, including stale ones, keyed by the message's hash code.  These are bounded by
+    /// capacity here rather than by the store, since stale entries don't count towards it.
+    entries: TimedStore<GroupedEntry>,
+    /// The live groups, keyed by hash code.
+    groups: HashMap<u64, GroupState>,
+    next_group_id: u64,
+    /// The number of entries belonging to removed groups which haven't yet been dropped.
+    stale: usize,
+    capacity: Option<usize>,
+    phantom: PhantomData<(Message, Group)>,
+}
+
+struct GroupState {
+    /// Identifies this incarnation of the group, so that entries from before it was last removed
+    /// can be told apart.
+    id: u64,
+    len: usize,
+}
+
+struct GroupedEntry {
+    group: u64,
+    group_id: u64,
+    /// How many copies of this message have been seen in this group before this one.
+    count: usize,
+}
+
+impl<Message: Hash, Group: Hash> GroupedFilter<Message, Group> {
+    /// Constructor for capacity based `GroupedFilter`.
+    pub fn with_capacity(capacity: usize) -> GroupedFilter<Message, Group> {
+        GroupedFilter::new(Some(capacity), None)
+    }
+
+    /// Constructor for time based `GroupedFilter`.
+    pub fn with_expiry_duration(time_to_live: Duration) -> GroupedFilter<Message, Group> {
+        GroupedFilter::new(None, Some(time_to_live))
+    }
+
+    /// Constructor for dual-feature capacity and time based `GroupedFilter`.
+    pub fn with_expiry_duration_and_capacity(time_to_live: Duration,
+                                             capacity: usize)
+                                             -> GroupedFilter<Message, Group> {
+        GroupedFilter::new(Some(capacity), Some(time_to_live))
+    }
+
+    /// Adds a message to `group`.
+    ///
+    /// As with `MessageFilter::insert`, if `message` already exists in the group its expiry time
+    /// is updated and it is moved to the back of the FIFO queue again.  The return value is the
+    /// number of times this specific message has already been added to this group.
+    pub fn insert(&mut self, group: &Group, message: &Message) -> usize {
+        self.remove_expired();
+        let group = hash(group);
+        let hash_code = hash(message);
+        if let Some(index) = self.position(group, hash_code) {
+            let index = self.entries.refresh(index);
+            let entry = self.entries.value_mut(index);
+            entry.count += 1;
+            return entry.count;
+        }
+
+        let next_group_id = self.next_group_id;
+        let group_id = {
+            let state = self.groups.entry(group).or_insert(GroupState {
+                id: next_group_id,
+                len: 0,
+            });
+            state.len += 1;
+            state.id
+        };
+        if group_id == next_group_id {
+            self.next_group_id += 1;
+        }
+        let _ = self.entries.push(hash_code,
+                                  GroupedEntry {
+                                      group: group,
+                                      group_id: group_id,
+                                      count: 0,
+                                  });
+        self.remove_excess();
+        0
+    }
+
+    /// Removes any expired messages, then removes the specified message from `group`.
+    pub fn remove(&mut self, group: &Group, message: &Message) {
+        self.remove_expired();
+        if let Some(index) = self.position(hash(group), hash(message)) {
+            let entry = self.entries.remove_at(index);
+            self.drop_live(entry.group);
+        }
+    }
+
+    /// Removes all messages in `group`.
+    pub fn remove_group(&mut self, group: &Group) {
+        if let Some(state) = self.groups.remove(&hash(group)) {
+            self.stale += state.len;
+        }
+        if self.stale * 2 > self.entries.len() {
+            let groups = &self.groups;
+            self.entries.retain(|entry| is_live(groups, entry));
+            self.stale = 0;
+        }
+    }
+
+    /// Returns the number of times this message has already been inserted into `group`.
+    pub fn count(&self, group: &Group, message: &Message) -> usize {
+        self.position(hash(group), hash(message)).map_or(0, |index| self.entries.value(index).count)
+    }
+
+    /// Removes any expired messages, then returns whether `message` exists in `group` or not.
+    pub fn contains(&mut self, group: &Group, message: &Message) -> bool {
+        self.remove_expired();
+        self.position(hash(group), hash(message)).is_some()
+    }
+
+    /// Returns the number of messages in `group`.
+    pub fn group_len(&self, group: &Group) -> usize {
+        self.groups.get(&hash(group)).map_or(0, |state| state.len)
+    }
+
+    /// Returns the size of the filter, i.e. the number of added messages across all groups.
+    pub fn len(&self) -> usize {
+        self.entries.len() - self.stale
+    }
+
+    /// Returns whether there are no entries in the filter.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clears the filter, removing all groups.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.groups.clear();
+        self.stale = 0;
+    }
+
+    /// Removes any expired messages.
+    pub fn remove_expired(&mut self) {
+        let expired = self.entries.take_expired();
+        self.drop_entries(expired);
+    }
+
+    fn new(capacity: Option<usize>,
+           time_to_live: Option<Duration>)
+           -> GroupedFilter<Message, Group> {
+        GroupedFilter {
+            entries: TimedStore::new(None, time_to_live),
+            groups: HashMap::new(),
+            next_group_id: 0,
+            stale: 0,
+            capacity: capacity,
+            phantom: PhantomData,
+        }
+    }
+
+    fn position(&self, group: u64, hash_code: u64) -> Option<usize> {
+        let group_id = match self.groups.get(&group) {
+            Some(state) => state.id,
+            None => return None,
+        };
+        self.entries.position_where(|entry_hash_code, entry| {
+            entry_hash_code == hash_code && entry.group == group && entry.group_id == group_id
+        })
+    }
+
+    fn remove_excess(&mut self) {
+        if let Some(capacity) = self.capacity {
+            if self.len() > capacity {
+                // Drop the oldest live entry, along with any stale ones in front of it.
+                let oldest_live = {
+                    let groups = &self.groups;
+                    self.entries.position_where(|_, entry| is_live(groups, entry))
+                };
+                if let Some(index) = oldest_live {
+                    let dropped = self.entries.take_front(index + 1);
+                    self.drop_entries(dropped);
+                }
+            }
+        }
+    }
+
+    /// Updates the group lengths and stale count after `dropped` have been removed.
+    fn drop_entries(&mut self, dropped: Vec<GroupedEntry>) {
+        for entry in dropped {
+            if is_live(&self.groups, &entry) {
+                self.drop_live(entry.group);
+            } else {
+                self.stale -= 1;
+            }
+        }
+    }
+
+    /// Updates `group`'s length after one of its live entries has been dropped.
+    fn drop_live(&mut self, group: u64) {
+        let now_empty = match self.groups.get_mut(&group) {
+            Some(state) => {
+                state.len -= 1;
+                state.len == 0
+            }
+            None => false,
+        };
+        if now_empty {
+            let _ = self.groups.remove(&group);
+        }
+    }
+}
+
+fn is_live(groups: &HashMap<u64, GroupState>, entry: &GroupedEntry) -> bool {
+    groups.get(&entry.group).map_or(false, |state| state.id == entry.group_id)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn remove_group() {
+        let mut filter = GroupedFilter::<u32, &str>::with_capacity(10);
+        for message in 0..4 {
+            assert_eq!(0, filter.insert(&"a", &message));
+            assert_eq!(0, filter.insert(&"b", &message));
+        }
+        assert_eq!(1, filter.insert(&"a", &0));
+        assert_eq!(1, filter.count(&"a", &0));
+        assert_eq!(0, filter.count(&"b", &0) + filter.count(&"c", &0));
+        assert_eq!(8, filter.len());
+
+        filter.remove_group(&"a");
+        assert!(!filter.contains(&"a", &0));
+        assert!(filter.contains(&"b", &0));
+        assert_eq!(0, filter.group_len(&"a"));
+        assert_eq!(4, filter.len());
+
+        // The group can be reused, without its old entries reappearing.
+        assert_eq!(0, filter.insert(&"a", &0));
+        assert_eq!(1, filter.group_len(&"a"));
+        assert_eq!(5, filter.len());
+
+        // Capacity counts live entries only, and stale entries are dropped along the way.
+        for message in 10..20 {
+            let _ = filter.insert(&"c", &message);
+        }
+        assert_eq!(10, filter.len());
+        assert_eq!(0, filter.group_len(&"b"));
+        assert_eq!(10, filter.group_len(&"c"));
+    }
+}
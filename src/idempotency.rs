@@ -0,0 +1,278 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::hash::Hash;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use super::expiring_map::{Entry, ExpiringMap};
+
+/// The state of a request in an `IdempotencyStore`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IdempotencyState<Response> {
+    /// The request is being processed.
+    InFlight,
+    /// The request has been processed, giving this response.
+    Completed(Response),
+}
+
+/// Identifies the request which `IdempotencyStore::begin` put in flight, so that only that request
+/// can complete or abort it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdempotencyToken(usize);
+
+/// The state of each key, along with the token of the request which last began it.
+type States<Key, Response> = ExpiringMap<Key, (IdempotencyToken, IdempotencyState<Response>)>;
+
+/// Store of idempotency keys, giving exactly-once processing of requests which carry them.
+///
+/// The first request with a given key is marked as in flight and should be processed; it must
+/// then be either completed with its response or aborted, using the token it was given.  Later
+/// requests with the same key get the stored response, or if the first is still in flight, can
+/// either treat that as a conflict or wait for it to finish.
+///
+/// Keys are bounded by time to live, and optionally capacity, in the same way as the messages in
+/// a `MessageFilter`.  The time to live applies to in-flight requests too, so that one whose
+/// processing never finishes can eventually be retried; it should therefore comfortably exceed
+/// the time taken to process a request.  Completing a request restarts its time to live.
+///
+/// Note that dropping a key for capacity breaks the exactly-once guarantee for it, in flight or
+/// not: a later request with the key is processed again, and if it was in flight its eventual
+/// `complete` is ignored.  The capacity should therefore comfortably exceed the number of keys
+/// expected within the time to live.
+///
+/// The store can be shared between threads, e.g. in an `Arc`.
+pub struct IdempotencyStore<Key, Response> {
+    states: Mutex<States<Key, Response>>,
+    finished: Condvar,
+    next_token: AtomicUsize,
+}
+
+impl<Key: Hash + Eq, Response: Clone> IdempotencyStore<Key, Response> {
+    /// Constructor for time based `IdempotencyStore`.
+    pub fn with_expiry_duration(time_to_live: Duration) -> IdempotencyStore<Key, Response> {
+        IdempotencyStore::new(ExpiringMap::with_expiry_duration(time_to_live))
+    }
+
+    /// Constructor for dual-feature capacity and time based `IdempotencyStore`.
+    pub fn with_expiry_duration_and_capacity(time_to_live: Duration,
+                                             capacity: usize)
+                                             -> IdempotencyStore<Key, Response> {
+        IdempotencyStore::new(ExpiringMap::with_expiry_duration_and_capacity(time_to_live,
+                                                                              capacity))
+    }
+
+    /// Starts a request with idempotency key `key`.
+    ///
+    /// Returns a token if this is the first request with the key, in which case it's now in flight
+    /// and the caller should process it, then pass the token to `complete` or `abort`.  Otherwise
+    /// returns the key's current state without waiting.
+    pub fn begin(&self, key: Key) -> Result<IdempotencyToken, IdempotencyState<Response>> {
+        let mut states = self.lock();
+        self.start(&mut states, key)
+    }
+
+    /// As `begin`, except that if the key is in flight, waits for up to `timeout` for it to finish.
+    ///
+    /// If the in-flight request is aborted meanwhile, this request becomes the one in flight and a
+    /// token is returned.  If it's still in flight after `timeout`, `Err(InFlight)` is returned.
+    pub fn begin_or_wait(&self,
+                         key: Key,
+                         timeout: Duration)
+                         -> Result<IdempotencyToken, IdempotencyState<Response>> {
+        let deadline = Instant::now() + timeout;
+        let mut states = self.lock();
+        loop {
+            let in_flight = match states.get(&key) {
+                Some(&(_, IdempotencyState::InFlight)) => true,
+                _ => false,
+            };
+            let now = Instant::now();
+            if !in_flight {
+                return self.start(&mut states, key);
+            } else if now >= deadline {
+                return Err(IdempotencyState::InFlight);
+            }
+            states = match self.finished.wait_timeout(states, deadline - now) {
+                Ok((states, _)) => states,
+                Err(error) => error.into_inner().0,
+            };
+        }
+    }
+
+    /// Records `response` as the result of the in-flight request with key `key`, which was given
+    /// `token` by `begin`.
+    ///
+    /// Returns `false`, without recording the response, if that request is no longer in flight,
+    /// e.g. because it has already been completed, or was aborted or dropped from the store, even
+    /// if the key has since been started again by another request.
+    pub fn complete(&self, key: Key, token: IdempotencyToken, response: Response) -> bool {
+        let completed = {
+            let mut states = self.lock();
+            let in_flight = is_in_flight(&states, &key, token);
+            if in_flight {
+                let _ = states.insert(key, (token, IdempotencyState::Completed(response)));
+            }
+            in_flight
+        };
+        self.finished.notify_all();
+        completed
+    }
+
+    /// Abandons the in-flight request with key `key`, which was given `token` by `begin`, so that a
+    /// later request with the key will be processed.  Has no effect if that request is no longer in
+    /// flight.
+    pub fn abort(&self, key: &Key, token: IdempotencyToken) {
+        {
+            let mut states = self.lock();
+            if is_in_flight(&states, key, token) {
+                let _ = states.remove(key);
+            }
+        }
+        self.finished.notify_all();
+    }
+
+    /// Returns the current state for `key`, or `None` if it's unknown.
+    pub fn get(&self, key: &Key) -> Option<IdempotencyState<Response>> {
+        self.lock().get(key).map(|&(_, ref state)| state.clone())
+    }
+
+    /// Returns the number of keys in the store.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns whether there are no keys in the store.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn new(states: States<Key, Response>) -> IdempotencyStore<Key, Response> {
+        IdempotencyStore {
+            states: Mutex::new(states),
+            finished: Condvar::new(),
+            next_token: AtomicUsize::new(0),
+        }
+    }
+
+    fn lock<'a>(&'a self) -> MutexGuard<'a, States<Key, Response>> {
+        // The map is always left consistent, so it's safe to carry on after a panic elsewhere.
+        self.states.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn start(&self,
+             states: &mut States<Key, Response>,
+             key: Key)
+             -> Result<IdempotencyToken, IdempotencyState<Response>> {
+        match states.entry(key) {
+            Entry::Occupied(entry) => Err(entry.get().1.clone()),
+            Entry::Vacant(entry) => {
+                let token = IdempotencyToken(self.next_token.fetch_add(1, Ordering::Relaxed));
+                let _ = entry.insert((token, IdempotencyState::InFlight));
+                Ok(token)
+            }
+        }
+    }
+}
+
+/// Returns whether `key` is in flight for the request which was given `token`.
+fn is_in_flight<Key, Response>(states: &States<Key, Response>,
+                               key: &Key,
+                               token: IdempotencyToken)
+                               -> bool
+    where Key: Hash + Eq
+{
+    match states.get(key) {
+        Some(&(in_flight, IdempotencyState::InFlight)) => in_flight == token,
+        _ => false,
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn exactly_once() {
+        let store = IdempotencyStore::<u32, String>::with_expiry_duration(Duration::from_secs(60));
+        let token = store.begin(0).expect("Request should be new.");
+        assert_eq!(Err(IdempotencyState::InFlight), store.begin(0));
+        assert!(store.complete(0, token, "done".to_owned()));
+        assert_eq!(Err(IdempotencyState::Completed("done".to_owned())), store.begin(0));
+        // A stale completion doesn't overwrite the response.
+        assert!(!store.complete(0, token, "again".to_owned()));
+        store.abort(&0, token);
+        assert_eq!(Some(IdempotencyState::Completed("done".to_owned())), store.get(&0));
+
+        // An aborted request can be retried.
+        let token = store.begin(1).expect("Request should be new.");
+        store.abort(&1, token);
+        assert_eq!(None, store.get(&1));
+        assert!(store.begin(1).is_ok());
+        assert_eq!(2, store.len());
+
+        // Keys dropped for capacity can't be completed.
+        let time_to_live = Duration::from_secs(60);
+        let store = IdempotencyStore::<u32, String>::with_expiry_duration_and_capacity(time_to_live,
+                                                                                       1);
+        let token = store.begin(0).expect("Request should be new.");
+        assert!(store.begin(1).is_ok());
+        assert!(!store.complete(0, token, "done".to_owned()));
+        assert_eq!(None, store.get(&0));
+    }
+
+    #[test]
+    fn expired_request_restarted() {
+        let store =
+            IdempotencyStore::<u32, String>::with_expiry_duration(Duration::from_millis(50));
+        let stale = store.begin(0).expect("Request should be new.");
+        thread::sleep(Duration::from_millis(60));
+        let token = store.begin(0).expect("Expired request should be restarted.");
+        assert!(stale != token);
+
+        // The expired request can neither abort nor complete the restarted one.
+        store.abort(&0, stale);
+        assert_eq!(Some(IdempotencyState::InFlight), store.get(&0));
+        assert!(!store.complete(0, stale, "stale".to_owned()));
+        assert!(store.complete(0, token, "done".to_owned()));
+        assert_eq!(Some(IdempotencyState::Completed("done".to_owned())), store.get(&0));
+    }
+
+    #[test]
+    fn duplicates_wait() {
+        let time_to_live = Duration::from_secs(60);
+        let store = Arc::new(IdempotencyStore::<u32, u32>::with_expiry_duration(time_to_live));
+        let token = store.begin(0).expect("Request should be new.");
+
+        let waiter_store = store.clone();
+        let waiter = thread::spawn(move || waiter_store.begin_or_wait(0, Duration::from_secs(10)));
+        thread::sleep(Duration::from_millis(50));
+        assert!(store.complete(0, token, 42));
+        assert_eq!(Err(IdempotencyState::Completed(42)),
+                   waiter.join().expect("Thread panicked."));
+
+        // Waiting gives up after the timeout.
+        assert!(store.begin(1).is_ok());
+        assert_eq!(Err(IdempotencyState::InFlight),
+                   store.begin_or_wait(1, Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::hash::Hash;
+use std::time::Duration;
+use super::MessageFilter;
+
+/// Extension methods for deduplicating an `Iterator` in bounded memory via a `MessageFilter`.
+pub trait DedupIterExt: Iterator + Sized {
+    /// Yields only the first occurrence of each item amongst the last `capacity` distinct items.
+    ///
+    /// An item seen again after being pushed out of the window by `capacity` newer items is
+    /// yielded again.
+    fn dedup_window(self, capacity: usize) -> DedupWindow<Self>
+        where Self::Item: Hash
+    {
+        DedupWindow {
+            iter: self,
+            filter: MessageFilter::with_capacity(capacity),
+        }
+    }
+
+    /// Yields only the first occurrence of each key returned by `key_fn` within `time_to_live`.
+    ///
+    /// As with `MessageFilter::insert`, a repeated key refreshes its expiry time.
+    fn dedup_ttl<Key, F>(self, time_to_live: Duration, key_fn: F) -> DedupTtl<Self, Key, F>
+        where Key: Hash,
+              F: FnMut(&Self::Item) -> Key
+    {
+        DedupTtl {
+            iter: self,
+            filter: MessageFilter::with_expiry_duration(time_to_live),
+            key_fn: key_fn,
+        }
+    }
+}
+
+impl<I: Iterator> DedupIterExt for I {}
+
+/// Iterator returned by `DedupIterExt::dedup_window`.
+pub struct DedupWindow<I: Iterator> {
+    iter: I,
+    filter: MessageFilter<I::Item>,
+}
+
+impl<I: Iterator> Iterator for DedupWindow<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let filter = &mut self.filter;
+        self.iter.by_ref().find(|item| filter.insert(item) == 0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+/// Iterator returned by `DedupIterExt::dedup_ttl`.
+pub struct DedupTtl<I, Key, F> {
+    iter: I,
+    filter: MessageFilter<Key>,
+    key_fn: F,
+}
+
+impl<I, Key, F> Iterator for DedupTtl<I, Key, F>
+    where I: Iterator,
+          F: FnMut(&I::Item) -> Key
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let filter = &mut self.filter;
+        let key_fn = &mut self.key_fn;
+        self.iter.by_ref().find(|item| filter.insert(&key_fn(item)) == 0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn window() {
+        let items = vec![1, 2, 3, 4, 1, 4];
+        // "1" is pushed out of the window by "4", so its second occurrence is yielded.
+        assert_eq!(vec![1, 2, 3, 4, 1],
+                   items.into_iter().dedup_window(3).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn time_to_live() {
+        let time_to_live = Duration::from_millis(100);
+        let mut deduped = vec![(1, 'a'), (1, 'b'), (2, 'c'), (1, 'd')]
+            .into_iter()
+            .chain(Some((1, 'e')).into_iter().inspect(|_| thread::sleep(time_to_live)))
+            .dedup_ttl(time_to_live, |&(id, _)| id);
+        assert_eq!(Some((1, 'a')), deduped.next());
+        assert_eq!(Some((2, 'c')), deduped.next());
+        // "1" has expired by the time "e" is reached.
+        assert_eq!(Some((1, 'e')), deduped.next());
+        assert_eq!(None, deduped.next());
+    }
+}
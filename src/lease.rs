@@ -0,0 +1,294 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// A message reserved for processing via `MessageFilter::reserve`.
+///
+/// While the lease is held the message behaves as though it had been inserted.  The lease must be
+/// committed once the message has been processed, which adds it to the filter, along with any
+/// further copies inserted meanwhile.  If the lease is dropped (or explicitly released) instead,
+/// or isn't committed before it times out, the reservation lapses, those copies are forgotten,
+/// and the message can be reserved again, e.g. when it's redelivered.
+///
+/// The lease doesn't borrow the filter, so it can be held while the filter is used elsewhere,
+/// e.g. from behind a `Mutex`.  A committed message is reported by the filter straight away, though
+/// it's only moved into the filter's entries the next time the filter is modified.  Clearing the
+/// filter releases all its leases, and discards any committed messages not yet moved.
+pub struct Lease {
+    table: Arc<Mutex<Leases>>,
+    id: u64,
+}
+
+impl Lease {
+    /// Commits the lease, marking the message as seen.
+    ///
+    /// Returns `false` if the lease had already timed out, in which case the message isn't added
+    /// to the filter.
+    pub fn commit(self) -> bool {
+        let committed = {
+            let mut leases = lock(&self.table);
+            let now = Instant::now();
+            match leases.held.iter().position(|held| held.id == self.id) {
+                Some(index) => {
+                    let held = leases.held.remove(index);
+                    if held.deadline > now {
+                        leases.committed.push((held.hash_code, held.copies));
+                        true
+                    } else {
+                        false
+                    }
+                }
+                None => false,
+            }
+        };
+        // The reservation has been removed, so dropping `self` now has no effect.
+        committed
+    }
+
+    /// Releases the lease without marking the message as seen.  This is the same as dropping it.
+    pub fn release(self) {}
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let mut leases = lock(&self.table);
+        leases.held.retain(|held| held.id != self.id);
+    }
+}
+
+/// The leases granted by a single `MessageFilter`, shared with the outstanding `Lease`s.
+pub struct LeaseTable {
+    table: Arc<Mutex<Leases>>,
+}
+
+struct Leases {
+    next_id: u64,
+    held: Vec<Held>,
+    /// Hash codes of the messages whose leases have been committed, but which haven't yet been
+    /// added to the filter, along with how many copies of each were seen.
+    committed: Vec<(u64, usize)>,
+}
+
+struct Held {
+    id: u64,
+    hash_code: u64,
+    deadline: Instant,
+    /// How many copies of the message have been seen, including the reserved one.
+    copies: usize,
+}
+
+impl LeaseTable {
+    pub fn new() -> LeaseTable {
+        LeaseTable {
+            table: Arc::new(Mutex::new(Leases {
+                next_id: 0,
+                held: vec![],
+                committed: vec![],
+            })),
+        }
+    }
+
+    /// Grants a lease on `hash_code` for `duration`, unless there's already one in force.
+    pub fn grant(&self, hash_code: u64, duration: Duration) -> Option<Lease> {
+        let mut leases = lock(&self.table);
+        let now = Instant::now();
+        leases.held.retain(|held| held.deadline > now);
+        if leases.held.iter().any(|held| held.hash_code == hash_code) {
+            return None;
+        }
+        let id = leases.next_id;
+        leases.next_id += 1;
+        leases.held.push(Held {
+            id: id,
+            hash_code: hash_code,
+            deadline: now + duration,
+            copies: 1,
+        });
+        Some(Lease {
+            table: self.table.clone(),
+            id: id,
+        })
+    }
+
+    /// Returns whether there's a lease in force on `hash_code`.
+    pub fn is_held(&self, hash_code: u64) -> bool {
+        let now = Instant::now();
+        lock(&self.table)
+            .held
+            .iter()
+            .any(|held| held.hash_code == hash_code && held.deadline > now)
+    }
+
+    /// Counts another copy of `hash_code` if there's a lease in force on it, returning the number
+    /// of copies seen before this one.
+    pub fn add_copy(&self, hash_code: u64) -> Option<usize> {
+        let mut leases = lock(&self.table);
+        let now = Instant::now();
+        leases.held
+            .iter_mut()
+            .find(|held| held.hash_code == hash_code && held.deadline > now)
+            .map(|held| {
+                held.copies += 1;
+                held.copies - 1
+            })
+    }
+
+    /// Returns the number of copies of `hash_code` seen under a lease which is in force, or which
+    /// has been committed since `take_committed` was last called.
+    pub fn copies(&self, hash_code: u64) -> usize {
+        let leases = lock(&self.table);
+        let now = Instant::now();
+        let held = leases.held
+            .iter()
+            .filter(|held| held.hash_code == hash_code && held.deadline > now)
+            .map(|held| held.copies)
+            .sum::<usize>();
+        held +
+        leases.committed
+            .iter()
+            .filter(|&&(committed, _)| committed == hash_code)
+            .map(|&(_, copies)| copies)
+            .sum::<usize>()
+    }
+
+    /// Returns the messages committed since this was last called, with how many copies of each
+    /// were seen.
+    pub fn take_committed(&self) -> Vec<(u64, usize)> {
+        mem::replace(&mut lock(&self.table).committed, vec![])
+    }
+
+    /// Returns the hash codes of the messages committed since `take_committed` was last called,
+    /// without taking them.
+    pub fn committed(&self) -> Vec<u64> {
+        lock(&self.table).committed.iter().map(|&(hash_code, _)| hash_code).collect()
+    }
+
+    /// Releases all held leases and discards any committed messages.
+    pub fn clear(&self) {
+        let mut leases = lock(&self.table);
+        leases.held.clear();
+        leases.committed.clear();
+    }
+}
+
+fn lock<'a>(table: &'a Mutex<Leases>) -> MutexGuard<'a, Leases> {
+    // The table is always left consistent, so it's safe to carry on after a panic elsewhere.
+    table.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+    use MessageFilter;
+
+    #[test]
+    fn commit_release_and_timeout() {
+        let lease_duration = Duration::from_secs(60);
+        let mut filter = MessageFilter::<usize>::with_capacity(10);
+        let lease = filter.reserve(&0, lease_duration).expect("Failed to reserve.");
+        assert!(filter.reserve(&0, lease_duration).is_none());
+        assert!(filter.contains(&0));
+        assert_eq!(0, filter.len());
+
+        // Releasing the lease allows the message to be reserved again.
+        lease.release();
+        assert!(!filter.contains(&0));
+        let lease = filter.reserve(&0, lease_duration).expect("Failed to reserve.");
+        assert!(lease.commit());
+        assert!(filter.contains(&0));
+        assert_eq!(1, filter.len());
+        assert!(filter.reserve(&0, lease_duration).is_none());
+
+        // A lease which times out lapses, and can no longer be committed.
+        let lease = filter.reserve(&1, Duration::from_millis(50)).expect("Failed to reserve.");
+        thread::sleep(Duration::from_millis(60));
+        assert!(!filter.contains(&1));
+        let second_lease = filter.reserve(&1, lease_duration).expect("Failed to reserve.");
+        assert!(!lease.commit());
+        drop(second_lease);
+        assert!(!filter.contains(&1));
+    }
+
+    #[test]
+    fn committed_lease_is_reported_immediately() {
+        let lease_duration = Duration::from_secs(60);
+        let mut filter = MessageFilter::<usize>::with_capacity(10)
+            .with_windowed_counts(lease_duration);
+        let _ = filter.insert(&0);
+        assert!(filter.reserve(&0, lease_duration).is_none());
+        let lease = filter.reserve(&1, lease_duration).expect("Failed to reserve.");
+        assert!(lease.commit());
+        assert_eq!(2, filter.len());
+        assert!(!filter.is_empty());
+        assert_eq!(0, filter.count(&1));
+        assert_eq!(1, filter.count_in_window(&1, lease_duration));
+
+        // Moving the committed message into the filter's entries doesn't change anything.
+        assert!(filter.contains(&1));
+        assert_eq!(2, filter.len());
+        assert_eq!(0, filter.count(&1));
+        assert_eq!(1, filter.count_in_window(&1, lease_duration));
+    }
+
+    #[test]
+    fn clear_releases_leases() {
+        let lease_duration = Duration::from_secs(60);
+        let mut filter = MessageFilter::<usize>::with_capacity(10);
+        let committed = filter.reserve(&0, lease_duration).expect("Failed to reserve.");
+        let held = filter.reserve(&1, lease_duration).expect("Failed to reserve.");
+        assert!(committed.commit());
+        filter.clear();
+        assert!(filter.is_empty());
+        assert!(!filter.contains(&0));
+        assert!(!filter.contains(&1));
+
+        // The released lease can no longer be committed, and the message can be reserved again.
+        assert!(!held.commit());
+        assert!(!filter.contains(&1));
+        assert!(filter.reserve(&1, lease_duration).is_some());
+    }
+    #[test]
+    fn insert_while_reserved() {
+        let lease_duration = Duration::from_secs(60);
+        let mut filter = MessageFilter::<usize>::with_capacity(10);
+        let lease = filter.reserve(&0, lease_duration).expect("Failed to reserve.");
+        assert_eq!(0, filter.count(&0));
+
+        // A copy is reported as a duplicate, but isn't added until the lease is committed.
+        assert_eq!(1, filter.insert(&0));
+        assert_eq!(1, filter.count(&0));
+        assert_eq!(0, filter.len());
+        assert!(lease.commit());
+        assert_eq!(1, filter.count(&0));
+        assert_eq!(1, filter.len());
+        assert_eq!(2, filter.insert(&0));
+        assert_eq!(1, filter.len());
+
+        // Releasing the lease forgets the copies too.
+        let lease = filter.reserve(&1, lease_duration).expect("Failed to reserve.");
+        assert_eq!(1, filter.insert(&1));
+        lease.release();
+        assert_eq!(0, filter.count(&1));
+        assert_eq!(0, filter.insert(&1));
+    }
+}
@@ -68,12 +68,77 @@
 #![cfg_attr(feature="clippy", deny(clippy, clippy_pedantic))]
 #![cfg_attr(feature="clippy", allow(use_debug))]
 
+#[cfg(feature = "futures")]
+#[macro_use]
+extern crate futures;
 #[cfg(test)]
 extern crate rand;
-
+#[cfg(feature = "async")]
+extern crate tokio_core;
+
+mod anti_replay;
+mod bloom;
+mod concurrent_filter;
+mod count_min;
+mod cuckoo;
+mod entry;
+mod error;
+mod expiring_map;
+mod filter;
+mod gossip;
+mod grouped;
+mod idempotency;
+mod iter;
+mod lease;
+mod packed;
+mod quorum;
+mod quota;
+mod rate_limiter;
+mod reaper;
+mod replay_guard;
+mod rotating_bloom;
+mod scalable_bloom;
+mod stable_bloom;
+mod static_filter;
+mod timed_store;
+#[cfg(feature = "futures")]
+mod stream;
+
+pub use anti_replay::{AntiReplayWindow, SequenceStatus};
+pub use bloom::BloomMessageFilter;
+pub use concurrent_filter::ConcurrentFilter;
+pub use count_min::CountMinSketch;
+pub use cuckoo::CuckooFilter;
+pub use entry::{FilterEntry, OccupiedFilterEntry, ReservedFilterEntry, VacantFilterEntry};
+pub use error::FilterError;
+pub use expiring_map::{Entry, ExpiringMap, OccupiedEntry, VacantEntry};
+pub use filter::Filter;
+pub use gossip::GossipFilter;
+pub use grouped::GroupedFilter;
+pub use idempotency::{IdempotencyState, IdempotencyStore, IdempotencyToken};
+pub use iter::{DedupIterExt, DedupTtl, DedupWindow};
+pub use lease::Lease;
+pub use quorum::{QuorumFilter, QuorumStatus};
+pub use quota::QuotaFilter;
+pub use rate_limiter::{Decision, RateLimiter};
+#[cfg(feature = "async")]
+pub use reaper::AsyncReaper;
+pub use reaper::Reaper;
+pub use replay_guard::{NonceStatus, ReplayGuard};
+pub use rotating_bloom::RotatingBloomFilter;
+pub use scalable_bloom::ScalableBloomFilter;
+pub use stable_bloom::StableBloomFilter;
+pub use static_filter::{LayeredFilter, StaticFilter};
+#[cfg(feature = "futures")]
+pub use stream::{DedupStream, DedupStreamExt};
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher, SipHasher};
-use std::marker::PhantomData;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
+use lease::LeaseTable;
+use timed_store::TimedStore;
 
 
 fn hash<T: Hash>(t: &T) -> u64 {
@@ -82,46 +147,137 @@ fn hash<T: Hash>(t: &T) -> u64 {
     s.finish()
 }
 
+/// Derives a further hash from `hash_code`, for filters which need several independent hashes of
+/// each message.
+fn rehash(hash_code: u64, key: u64) -> u64 {
+    let mut s = DefaultHasher::new();
+    s.write_u64(key);
+    s.write_u64(hash_code);
+    s.finish()
+}
+
+/// Advances the xorshift generator `state`, returning the next pseudo-random value.  This is only
+/// used where the filters need cheap, not cryptographically secure, randomness.
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+
+/// Identity of a message, used by a `MessageFilter` in place of hashing the whole message.
+///
+/// This allows fields which don't affect a message's identity (e.g. a hop count or signatures) to
+/// be ignored by the filter without needing a custom `Hash` implementation for the message.  The
+/// identity is returned by value, so can be built from several fields, e.g. `(sender, seq)`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate message_filter;
+/// # fn main() {
+/// use ::message_filter::{MessageFilter, MessageId};
+///
+/// struct Message {
+///     sender: u64,
+///     seq: u32,
+///     hop_count: u8,
+/// }
+///
+/// impl MessageId for Message {
+///     type Id = (u64, u32);
+///     fn message_id(&self) -> (u64, u32) {
+///         (self.sender, self.seq)
+///     }
+/// }
+///
+/// let mut message_filter = MessageFilter::<Message>::with_capacity_by_id(10);
+/// assert_eq!(0, message_filter.insert(&Message { sender: 1, seq: 0, hop_count: 0 }));
+/// assert_eq!(1, message_filter.insert(&Message { sender: 1, seq: 0, hop_count: 1 }));
+/// # }
+/// ```
+pub trait MessageId {
+    /// The type of the identity.
+    type Id: Hash;
+    /// Returns the part of the message which identifies it.
+    fn message_id(&self) -> Self::Id;
+}
+
+fn hash_id<T: MessageId>(t: &T) -> u64 {
+    hash(&t.message_id())
+}
+
 
 /// Implementation of [message filter](index.html#message-filter).
 pub struct MessageFilter<Message> {
-    entries: Vec<TimestampedMessage>,
-    capacity: Option<usize>,
-    time_to_live: Option<Duration>,
-    phantom: PhantomData<Message>,
+    entries: TimedStore<Tally>,
+    max_window: Option<Duration>,
+    leases: Option<LeaseTable>,
+    by_id: bool,
+    hash_fn: fn(&Message) -> u64,
 }
 
 impl<Message: Hash> MessageFilter<Message> {
     /// Constructor for capacity based `MessageFilter`.
     pub fn with_capacity(capacity: usize) -> MessageFilter<Message> {
-        MessageFilter {
-            entries: vec![],
-            capacity: Some(capacity),
-            time_to_live: None,
-            phantom: PhantomData,
-        }
+        MessageFilter::new(Some(capacity), None, hash::<Message>)
     }
 
     /// Constructor for time based `MessageFilter`.
     pub fn with_expiry_duration(time_to_live: Duration) -> MessageFilter<Message> {
-        MessageFilter {
-            entries: vec![],
-            capacity: None,
-            time_to_live: Some(time_to_live),
-            phantom: PhantomData,
-        }
+        MessageFilter::new(None, Some(time_to_live), hash::<Message>)
     }
 
     /// Constructor for dual-feature capacity and time based `MessageFilter`.
     pub fn with_expiry_duration_and_capacity(time_to_live: Duration,
                                              capacity: usize)
                                              -> MessageFilter<Message> {
-        MessageFilter {
-            entries: vec![],
-            capacity: Some(capacity),
-            time_to_live: Some(time_to_live),
-            phantom: PhantomData,
-        }
+        MessageFilter::new(Some(capacity), Some(time_to_live), hash::<Message>)
+    }
+}
+
+impl<Message: MessageId> MessageFilter<Message> {
+    /// Constructor for capacity based `MessageFilter` which identifies messages by their
+    /// `MessageId`.
+    pub fn with_capacity_by_id(capacity: usize) -> MessageFilter<Message> {
+        MessageFilter::new_by_id(Some(capacity), None)
+    }
+
+    /// Constructor for time based `MessageFilter` which identifies messages by their `MessageId`.
+    pub fn with_expiry_duration_by_id(time_to_live: Duration) -> MessageFilter<Message> {
+        MessageFilter::new_by_id(None, Some(time_to_live))
+    }
+
+    /// Constructor for dual-feature capacity and time based `MessageFilter` which identifies
+    /// messages by their `MessageId`.
+    pub fn with_expiry_duration_and_capacity_by_id(time_to_live: Duration,
+                                                   capacity: usize)
+                                                   -> MessageFilter<Message> {
+        MessageFilter::new_by_id(Some(capacity), Some(time_to_live))
+    }
+
+    fn new_by_id(capacity: Option<usize>,
+                 time_to_live: Option<Duration>)
+                 -> MessageFilter<Message> {
+        let mut filter = MessageFilter::new(capacity, time_to_live, hash_id::<Message>);
+        filter.by_id = true;
+        filter
+    }
+}
+
+impl<Message> MessageFilter<Message> {
+    /// Enables `count_in_window` for windows of up to `max_window`.
+    ///
+    /// Each entry then records when it was inserted for as long as `max_window`, so this costs
+    /// memory in proportion to how often messages are repeated within that time.
+    pub fn with_windowed_counts(mut self, max_window: Duration) -> MessageFilter<Message> {
+        self.max_window = Some(max_window);
+        self
     }
 
     /// Adds a message to the filter.
@@ -133,122 +289,216 @@ impl<Message: Hash> MessageFilter<Message> {
     ///
     /// The return value is the number of times this specific message has already been added.
     pub fn insert(&mut self, message: &Message) -> usize {
-        self.remove_expired();
-        let hash_code = hash(message);
-        if let Some(index) = self.entries.iter().position(|ref t| t.hash_code == hash_code) {
-            let mut timestamped_message = self.entries.remove(index);
-            timestamped_message.update_expiry_point(self.time_to_live);
-            let count = timestamped_message.increment_count();
-            self.entries.push(timestamped_message);
-            count
-        } else {
-            self.entries.push(TimestampedMessage::new(hash_code, self.time_to_live));
-            self.remove_excess();
-            0
+        let hash_code = (self.hash_fn)(message);
+        self.insert_unless_reserved(hash_code)
+    }
+
+    /// Reserves `message` for processing, for up to `lease`.
+    ///
+    /// Returns `None` if the message is already in the filter or reserved, otherwise a `Lease`
+    /// which should be committed once the message has been processed.  While the lease is held,
+    /// the message behaves as though it had been inserted, except that it's only kept if the lease
+    /// is committed: `contains` reports it, and inserting it again returns and counts a duplicate
+    /// as usual, but nothing is added to the filter until then.
+    pub fn reserve(&mut self, message: &Message, lease: Duration) -> Option<Lease> {
+        if self.contains(message) {
+            return None;
+        }
+        let hash_code = (self.hash_fn)(message);
+        if self.leases.is_none() {
+            self.leases = Some(LeaseTable::new());
+        }
+        self.leases.as_ref().and_then(|leases| leases.grant(hash_code, lease))
+    }
+
+    /// Inserts `hash_code`, or if it's reserved, counts another copy of it against the lease.
+    fn insert_unless_reserved(&mut self, hash_code: u64) -> usize {
+        if let Some(count) = self.leases.as_ref().and_then(|leases| leases.add_copy(hash_code)) {
+            return count;
         }
+        self.apply_leases();
+        self.insert_hash_code(hash_code)
+    }
+
+    fn insert_hash_code(&mut self, hash_code: u64) -> usize {
+        self.entries.remove_expired();
+        let position = self.entries.position(hash_code);
+        match position {
+            Some(index) => self.insert_at(index),
+            None => self.insert_new(hash_code),
+        }
+    }
+
+    /// Adds another copy of the message whose entry is at `index`, returning its previous count.
+    fn insert_at(&mut self, index: usize) -> usize {
+        let index = self.entries.refresh(index);
+        let max_window = self.max_window;
+        let tally = self.entries.value_mut(index);
+        tally.count += 1;
+        tally.record_sighting(max_window);
+        tally.count
+    }
+
+    /// Adds an entry for `hash_code`, which must not already have one.  Always returns 0.
+    fn insert_new(&mut self, hash_code: u64) -> usize {
+        let mut tally = Tally {
+            count: 0,
+            sightings: None,
+        };
+        tally.record_sighting(self.max_window);
+        let _ = self.entries.push(hash_code, tally);
+        0
     }
 
     /// Removes a message from the filter.
     ///
     /// Removes any expired messages, then removes the specified message from the filter.
     pub fn remove(&mut self, message: &Message) {
-        self.remove_expired();
-        let hash_code = hash(message);
-        if let Some(index) = self.entries.iter().position(|ref t| t.hash_code == hash_code) {
-            let _ = self.entries.remove(index);
-        }
+        self.apply_leases();
+        let _ = self.entries.remove((self.hash_fn)(message));
     }
 
     /// Returns the number of times this message has already been inserted.
     pub fn count(&self, message: &Message) -> usize {
-        let hash_code = hash(message);
-        self.entries.iter().find(|t| t.hash_code == hash_code).map_or(0, |t| t.count)
+        self.count_hash_code((self.hash_fn)(message))
     }
 
-    /// Removes any expired messages, then returns whether `message` exists in the filter or not.
+    /// Returns the number of times this message has been inserted within the last `window`,
+    /// including its first insertion.
+    ///
+    /// This is always 0 unless the filter was built using `with_windowed_counts`, and `window` is
+    /// limited to the `max_window` given there.  Note that re-inserting a message extends its
+    /// lifetime, so in a time based filter a message which is being repeated within its time to
+    /// live is never dropped, even once its windowed count has fallen.
+    pub fn count_in_window(&self, message: &Message, window: Duration) -> usize {
+        if self.max_window.is_none() {
+            return 0;
+        }
+        let hash_code = (self.hash_fn)(message);
+        let sightings = self.entries.get(hash_code).map_or(0, |tally| tally.sightings_in(window));
+        sightings + self.leases.as_ref().map_or(0, |leases| leases.copies(hash_code))
+    }
+
+    /// Removes any expired messages, then returns whether `message` exists in the filter, or is
+    /// reserved via `reserve`, or not.
     pub fn contains(&mut self, message: &Message) -> bool {
-        self.remove_expired();
-        let hash_code = hash(message);
-        self.entries.iter().any(|ref entry| entry.hash_code == hash_code)
+        self.apply_leases();
+        self.entries.remove_expired();
+        let hash_code = (self.hash_fn)(message);
+        self.entries.position(hash_code).is_some() ||
+        self.leases.as_ref().map_or(false, |leases| leases.is_held(hash_code))
     }
 
     /// Returns the size of the filter, i.e. the number of added messages.
     pub fn len(&self) -> usize {
-        self.entries.len()
+        let mut committed = match self.leases {
+            Some(ref leases) => leases.committed(),
+            None => return self.entries.len(),
+        };
+        committed.sort();
+        committed.dedup();
+        committed.retain(|&hash_code| self.entries.position(hash_code).is_none());
+        let len = self.entries.len() + committed.len();
+        self.entries.capacity().map_or(len, |capacity| cmp::min(len, capacity))
     }
 
-    /// Clears the filter, removing all entries.
+    /// Clears the filter, removing all entries and releasing any leases granted by `reserve`.
     pub fn clear(&mut self) {
         self.entries.clear();
+        if let Some(ref leases) = self.leases {
+            leases.clear();
+        }
     }
 
     /// Returns whether there are no entries in the filter.
     pub fn is_empty(&self) -> bool {
-        self.entries.is_empty()
+        self.len() == 0
     }
 
-    fn remove_excess(&mut self) {
-        // If capacity is Some, remove the first entry if we're above the limit (should only ever be
-        // at most one entry above capacity).
-        if let Some(capacity) = self.capacity {
-            if self.entries.len() > capacity {
-                let _ = self.entries.remove(0);
-                debug_assert!(self.entries.len() == capacity);
-            }
+    /// Removes any expired messages.
+    ///
+    /// This is done automatically by `insert`, `remove` and `contains`, but can be called directly
+    /// to release the memory held by a time based filter which is otherwise idle.  See `Reaper` for
+    /// a helper which does this periodically.
+    pub fn remove_expired(&mut self) {
+        self.entries.remove_expired();
+    }
+
+    fn new(capacity: Option<usize>,
+           time_to_live: Option<Duration>,
+           hash_fn: fn(&Message) -> u64)
+           -> MessageFilter<Message> {
+        MessageFilter {
+            entries: TimedStore::new(capacity, time_to_live),
+            max_window: None,
+            leases: None,
+            by_id: false,
+            hash_fn: hash_fn,
         }
     }
 
-    fn remove_expired(&mut self) {
-        if self.time_to_live.is_some() {
-            let now = SystemTime::now();
-            // The entries are sorted from oldest to newest, so just split off the vector at the
-            // first unexpired entry and the returned vector is the remaining unexpired values.  If
-            // we don't find any unexpired value, just clear the vector.
-            if let Some(at) = self.entries.iter().position(|ref entry| entry.expiry_point > now) {
-                self.entries = self.entries.split_off(at)
-            } else {
-                self.entries.clear();
+    /// Returns the number of times `hash_code` has already been inserted, including copies seen
+    /// while it's reserved, and via leases which have been committed but not yet applied.
+    fn count_hash_code(&self, hash_code: u64) -> usize {
+        let pending = self.leases.as_ref().map_or(0, |leases| leases.copies(hash_code));
+        match self.entries.get(hash_code) {
+            Some(tally) => tally.count + pending,
+            None => pending.saturating_sub(1),
+        }
+    }
+
+    /// Adds the messages whose leases have been committed since this was last called, along with
+    /// any further copies seen while they were reserved.
+    fn apply_leases(&mut self) {
+        let committed = match self.leases {
+            Some(ref leases) => leases.take_committed(),
+            None => return,
+        };
+        for (hash_code, copies) in committed {
+            for _ in 0..copies {
+                let _ = self.insert_hash_code(hash_code);
             }
         }
     }
 }
 
-struct TimestampedMessage {
-    pub hash_code: u64,
-    pub expiry_point: SystemTime,
-    /// How many copies of this message have been seen before this one.
-    pub count: usize,
+/// What a `MessageFilter` records about each message.
+struct Tally {
+    /// How many copies of this message have been seen before the latest one.
+    count: usize,
+    /// When this message was inserted, from oldest to newest, if windowed counts are enabled.
+    /// This is boxed so that it only costs a pointer per entry otherwise.
+    sightings: Option<Box<VecDeque<Instant>>>,
 }
 
-impl TimestampedMessage {
-    pub fn new(hash_code: u64, time_to_live: Option<Duration>) -> TimestampedMessage {
-        TimestampedMessage {
-            hash_code: hash_code,
-            expiry_point: match time_to_live {
-                Some(time_to_live) => SystemTime::now() + time_to_live,
-                None => SystemTime::now(),
-            },
-            count: 0,
-        }
-    }
-
-    /// Updates the expiry point to set the given time to live from now.
-    pub fn update_expiry_point(&mut self, time_to_live: Option<Duration>) {
-        self.expiry_point = match time_to_live {
-            Some(time_to_live) => SystemTime::now() + time_to_live,
-            None => SystemTime::now(),
+impl Tally {
+    /// Records an insertion now if windowed counts are enabled, forgetting those more than
+    /// `max_window` ago.
+    fn record_sighting(&mut self, max_window: Option<Duration>) {
+        let max_window = match max_window {
+            Some(max_window) => max_window,
+            None => return,
         };
+        if self.sightings.is_none() {
+            self.sightings = Some(Box::new(VecDeque::new()));
+        }
+        if let Some(ref mut sightings) = self.sightings {
+            sightings.push_back(Instant::now());
+            while sightings.front().map_or(false, |sighting| sighting.elapsed() > max_window) {
+                let _ = sightings.pop_front();
+            }
+        }
     }
 
-    /// Increments the counter and returns its new value.
-    pub fn increment_count(&mut self) -> usize {
-        self.count += 1;
-        self.count
+    /// Returns the number of insertions within the last `window`.
+    fn sightings_in(&self, window: Duration) -> usize {
+        self.sightings.as_ref().map_or(0, |sightings| {
+            sightings.iter().rev().take_while(|sighting| sighting.elapsed() <= window).count()
+        })
     }
 }
 
 
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -261,8 +511,8 @@ mod test {
     fn size_only() {
         let size = rand::random::<u8>() as usize + 1;
         let mut msg_filter = MessageFilter::<usize>::with_capacity(size);
-        assert!(msg_filter.time_to_live.is_none());
-        assert_eq!(Some(size), msg_filter.capacity);
+        assert!(msg_filter.entries.time_to_live().is_none());
+        assert_eq!(Some(size), msg_filter.entries.capacity());
 
         // Add `size` messages - all should be added.
         for i in 0..size {
@@ -291,8 +541,8 @@ mod test {
     fn time_only() {
         let time_to_live = Duration::from_millis(rand::thread_rng().gen_range(50, 150));
         let mut msg_filter = MessageFilter::<usize>::with_expiry_duration(time_to_live);
-        assert_eq!(Some(time_to_live), msg_filter.time_to_live);
-        assert_eq!(None, msg_filter.capacity);
+        assert_eq!(Some(time_to_live), msg_filter.entries.time_to_live());
+        assert_eq!(None, msg_filter.entries.capacity());
 
         // Add 10 messages - all should be added.
         for i in 0..10 {
@@ -326,8 +576,8 @@ mod test {
         let time_to_live = Duration::from_millis(rand::thread_rng().gen_range(50, 150));
         let mut msg_filter =
             MessageFilter::<usize>::with_expiry_duration_and_capacity(time_to_live, size);
-        assert_eq!(Some(time_to_live), msg_filter.time_to_live);
-        assert_eq!(Some(size), msg_filter.capacity);
+        assert_eq!(Some(time_to_live), msg_filter.entries.time_to_live());
+        assert_eq!(Some(size), msg_filter.entries.capacity());
 
         for i in 0..1000 {
             // Check `size` has not been exceeded.
@@ -377,8 +627,8 @@ mod test {
         let time_to_live = Duration::from_millis(rand::thread_rng().gen_range(50, 150));
         let mut msg_filter = MessageFilter::<Temp>::with_expiry_duration_and_capacity(time_to_live,
                                                                                       size);
-        assert_eq!(Some(time_to_live), msg_filter.time_to_live);
-        assert_eq!(Some(size), msg_filter.capacity);
+        assert_eq!(Some(time_to_live), msg_filter.entries.time_to_live());
+        assert_eq!(Some(size), msg_filter.entries.capacity());
 
         for i in 0..1000 {
             // Check `size` has not been exceeded.
@@ -464,4 +714,63 @@ mod test {
         thread::sleep(sleep_duration);
         assert!(!time_filter.contains(&0));
     }
+
+    #[test]
+    fn windowed_counts() {
+        let window = Duration::from_millis(100);
+        let mut msg_filter = MessageFilter::<usize>::with_capacity(10).with_windowed_counts(window);
+        for _ in 0..3 {
+            let _ = msg_filter.insert(&0);
+        }
+        assert_eq!(3, msg_filter.count_in_window(&0, window));
+        assert_eq!(0, msg_filter.count_in_window(&1, window));
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(3, msg_filter.insert(&0));
+        assert_eq!(1, msg_filter.count_in_window(&0, Duration::from_millis(50)));
+        assert_eq!(4, msg_filter.count_in_window(&0, window));
+
+        // The earlier insertions drop out of the window, though the lifetime count remains.
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(1, msg_filter.count_in_window(&0, window));
+        assert_eq!(3, msg_filter.count(&0));
+
+        // Without windowed counts enabled, nothing is recorded.
+        let mut msg_filter = MessageFilter::<usize>::with_capacity(10);
+        let _ = msg_filter.insert(&0);
+        assert_eq!(0, msg_filter.count_in_window(&0, window));
+    }
+
+    #[test]
+    fn by_id() {
+        #[allow(dead_code)]
+        struct Message {
+            sender: u64,
+            seq: u32,
+            payload: Vec<u8>,
+        }
+
+        impl MessageId for Message {
+            type Id = (u64, u32);
+            fn message_id(&self) -> (u64, u32) {
+                (self.sender, self.seq)
+            }
+        }
+
+        let message = |sender, seq, payload| {
+            Message {
+                sender: sender,
+                seq: seq,
+                payload: payload,
+            }
+        };
+        let mut msg_filter = MessageFilter::<Message>::with_capacity_by_id(10);
+        assert_eq!(0, msg_filter.insert(&message(1, 0, vec![0])));
+        // Only the sender and sequence number identify a message, not its payload.
+        assert!(msg_filter.contains(&message(1, 0, vec![1])));
+        assert_eq!(1, msg_filter.insert(&message(1, 0, vec![1])));
+        assert_eq!(0, msg_filter.insert(&message(1, 1, vec![0])));
+        assert_eq!(0, msg_filter.insert(&message(2, 0, vec![0])));
+        assert_eq!(3, msg_filter.len());
+    }
 }
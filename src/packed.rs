@@ -0,0 +1,110 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// Fixed-length array of unsigned values, each `bits` wide, packed into 64-bit words.
+///
+/// This is the storage shared by the probabilistic filters: single bits for a Bloom filter, small
+/// counters or fingerprints for the others.
+pub struct PackedArray {
+    words: Vec<u64>,
+    bits: usize,
+    len: usize,
+}
+
+impl PackedArray {
+    /// Creates an array of `len` zeroed values, each `bits` wide.  `bits` must be in `1...32`.
+    pub fn new(len: usize, bits: usize) -> PackedArray {
+        assert!(bits >= 1 && bits <= 32,
+                "PackedArray values must be between 1 and 32 bits wide.");
+        PackedArray {
+            words: vec![0; (len * bits + 63) / 64],
+            bits: bits,
+            len: len,
+        }
+    }
+
+    /// Returns the value at `index`.
+    pub fn get(&self, index: usize) -> u64 {
+        debug_assert!(index < self.len);
+        let bit = index * self.bits;
+        let (word, offset) = (bit / 64, bit % 64);
+        let mut value = self.words[word] >> offset;
+        if offset + self.bits > 64 {
+            value |= self.words[word + 1] << (64 - offset);
+        }
+        value & self.max_value()
+    }
+
+    /// Sets the value at `index`, truncating `value` to the array's width.
+    pub fn set(&mut self, index: usize, value: u64) {
+        debug_assert!(index < self.len);
+        let mask = self.max_value();
+        let value = value & mask;
+        let bit = index * self.bits;
+        let (word, offset) = (bit / 64, bit % 64);
+        self.words[word] = self.words[word] & !(mask << offset) | value << offset;
+        if offset + self.bits > 64 {
+            let shift = 64 - offset;
+            self.words[word + 1] = self.words[word + 1] & !(mask >> shift) | value >> shift;
+        }
+    }
+
+    /// Returns the largest value which can be held.
+    pub fn max_value(&self) -> u64 {
+        (1 << self.bits) - 1
+    }
+
+    /// Returns the number of values in the array.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Resets every value to zero.
+    pub fn clear(&mut self) {
+        for word in &mut self.words {
+            *word = 0;
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn values_straddling_words() {
+        // 7-bit values regularly straddle the boundaries between words.
+        let mut array = PackedArray::new(100, 7);
+        assert_eq!(127, array.max_value());
+        for i in 0..100 {
+            array.set(i, i as u64 + 20);
+        }
+        for i in 0..100 {
+            assert_eq!((i as u64 + 20) & 127, array.get(i));
+        }
+
+        array.set(9, 0);
+        assert_eq!(0, array.get(9));
+        assert_eq!(28, array.get(8));
+        assert_eq!(30, array.get(10));
+
+        array.clear();
+        assert!((0..100).all(|i| array.get(i) == 0));
+    }
+}
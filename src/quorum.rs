@@ -0,0 +1,214 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::Duration;
+use super::hash;
+use super::timed_store::TimedStore;
+
+/// The outcome of `QuorumFilter::insert`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuorumStatus {
+    /// The threshold hasn't been reached yet.
+    Pending {
+        /// The number of distinct sources which have reported the message so far.
+        sources: usize,
+    },
+    /// This report took the message to the threshold.  This is returned once per message.
+    QuorumReached,
+    /// The threshold was already reached by earlier reports.
+    AlreadyReached,
+}
+
+/// Filter which detects when a message has been reported by a threshold number of distinct
+/// sources, e.g. for aggregating votes.
+///
+/// Repeated reports from the same source aren't counted again.  An incomplete tally expires
+/// `time_to_live` after the message was first reported, so that they don't accumulate; once
+/// expired, further reports of the message start a new tally.  Once a message reaches the
+/// threshold, its tally is replaced by a small marker so that `QuorumReached` is only ever returned
+/// once for it while the marker is held.  By default a marker expires `time_to_live` after the
+/// threshold was reached, after which further reports of the message start a new tally; use
+/// `with_reached_expiry` to hold markers for longer if reports can keep arriving after that.
+///
+/// Optionally, the number of tallies and of markers can each also be bounded by capacity, in which
+/// case the oldest is dropped when full.  A message whose marker is dropped in this way can reach
+/// the threshold again.
+pub struct QuorumFilter<Message, Source> {
+    tallies: TimedStore<Vec<u64>>,
+    reached: TimedStore<()>,
+    threshold: usize,
+    capacity: Option<usize>,
+    phantom: PhantomData<(Message, Source)>,
+}
+
+impl<Message: Hash, Source: Hash> QuorumFilter<Message, Source> {
+    /// Constructor for a `QuorumFilter` requiring `threshold` distinct sources, whose incomplete
+    /// tallies expire after `time_to_live`.
+    pub fn with_expiry_duration(threshold: usize,
+                                time_to_live: Duration)
+                                -> QuorumFilter<Message, Source> {
+        QuorumFilter::new(threshold, time_to_live, None)
+    }
+
+    /// Constructor for a `QuorumFilter` requiring `threshold` distinct sources, whose incomplete
+    /// tallies expire after `time_to_live` and of which at most `capacity` are held, along with at
+    /// most `capacity` markers of messages which have reached the threshold.
+    pub fn with_expiry_duration_and_capacity(threshold: usize,
+                                             time_to_live: Duration,
+                                             capacity: usize)
+                                             -> QuorumFilter<Message, Source> {
+        QuorumFilter::new(threshold, time_to_live, Some(capacity))
+    }
+
+    /// Makes the markers of messages which have reached the threshold expire `time_to_live` after
+    /// the threshold was reached, in place of the time to live of the incomplete tallies.
+    pub fn with_reached_expiry(mut self, time_to_live: Duration) -> QuorumFilter<Message, Source> {
+        self.reached = TimedStore::new(self.capacity, Some(time_to_live));
+        self
+    }
+
+    /// Records that `message` was reported by `source`.
+    pub fn insert(&mut self, message: &Message, source: &Source) -> QuorumStatus {
+        let hash_code = hash(message);
+        if self.reached.get(hash_code).is_some() {
+            return QuorumStatus::AlreadyReached;
+        }
+        let source = hash(source);
+        // Tallies are updated in place so that their expiry runs from the first report.
+        let reported = match self.tallies.get_mut(hash_code) {
+            Some(sources) => {
+                if !sources.contains(&source) {
+                    sources.push(source);
+                }
+                Some(sources.len())
+            }
+            None => None,
+        };
+        let sources = match reported {
+            Some(sources) => sources,
+            None => {
+                let _ = self.tallies.insert(hash_code, vec![source]);
+                1
+            }
+        };
+        if sources < self.threshold {
+            return QuorumStatus::Pending { sources: sources };
+        }
+        let _ = self.tallies.remove(hash_code);
+        let _ = self.reached.insert(hash_code, ());
+        QuorumStatus::QuorumReached
+    }
+
+    /// Returns the number of distinct sources which have reported `message`, or `None` if the
+    /// threshold has already been reached.
+    pub fn sources(&self, message: &Message) -> Option<usize> {
+        let hash_code = hash(message);
+        if self.reached.get(hash_code).is_some() {
+            return None;
+        }
+        Some(self.tallies.get(hash_code).map_or(0, |sources| sources.len()))
+    }
+
+    /// Returns the number of tallies, complete or not, in the filter.
+    pub fn len(&mut self) -> usize {
+        self.tallies.remove_expired();
+        self.reached.remove_expired();
+        self.tallies.len() + self.reached.len()
+    }
+
+    /// Returns whether there are no tallies in the filter.
+    pub fn is_empty(&mut self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clears the filter, removing all tallies.
+    pub fn clear(&mut self) {
+        self.tallies.clear();
+        self.reached.clear();
+    }
+
+    fn new(threshold: usize,
+           time_to_live: Duration,
+           capacity: Option<usize>)
+           -> QuorumFilter<Message, Source> {
+        QuorumFilter {
+            tallies: TimedStore::new(capacity, Some(time_to_live)),
+            reached: TimedStore::new(capacity, Some(time_to_live)),
+            threshold: cmp::max(threshold, 1),
+            capacity: capacity,
+            phantom: PhantomData,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn reached_once() {
+        let time_to_live = Duration::from_millis(100);
+        let mut filter = QuorumFilter::<&str, u8>::with_expiry_duration(3, time_to_live)
+            .with_reached_expiry(Duration::from_secs(60));
+        assert_eq!(QuorumStatus::Pending { sources: 1 }, filter.insert(&"vote", &1));
+        assert_eq!(QuorumStatus::Pending { sources: 1 }, filter.insert(&"vote", &1));
+        assert_eq!(QuorumStatus::Pending { sources: 2 }, filter.insert(&"vote", &2));
+        assert_eq!(Some(2), filter.sources(&"vote"));
+        assert_eq!(QuorumStatus::QuorumReached, filter.insert(&"vote", &3));
+        assert_eq!(QuorumStatus::AlreadyReached, filter.insert(&"vote", &4));
+        assert_eq!(None, filter.sources(&"vote"));
+
+        // Incomplete tallies expire.
+        assert_eq!(QuorumStatus::Pending { sources: 1 }, filter.insert(&"other", &1));
+        assert_eq!(2, filter.len());
+        thread::sleep(Duration::from_millis(110));
+        assert_eq!(1, filter.len());
+        assert_eq!(QuorumStatus::Pending { sources: 1 }, filter.insert(&"other", &2));
+
+        // Reached tallies have their own expiry.
+        assert_eq!(QuorumStatus::AlreadyReached, filter.insert(&"vote", &5));
+        assert_eq!(None, filter.sources(&"vote"));
+    }
+
+    #[test]
+    fn reached_expiry() {
+        // By default, reached tallies expire after the same time to live.
+        let time_to_live = Duration::from_millis(50);
+        let mut filter = QuorumFilter::<&str, u8>::with_expiry_duration(1, time_to_live);
+        assert_eq!(QuorumStatus::QuorumReached, filter.insert(&"vote", &1));
+        assert_eq!(QuorumStatus::AlreadyReached, filter.insert(&"vote", &2));
+        thread::sleep(Duration::from_millis(60));
+        assert!(filter.is_empty());
+        assert_eq!(QuorumStatus::QuorumReached, filter.insert(&"vote", &2));
+
+        let time_to_live = Duration::from_millis(100);
+        let mut filter = QuorumFilter::<&str, u8>::with_expiry_duration(2, time_to_live)
+            .with_reached_expiry(Duration::from_millis(50));
+        assert_eq!(QuorumStatus::Pending { sources: 1 }, filter.insert(&"vote", &1));
+        assert_eq!(QuorumStatus::QuorumReached, filter.insert(&"vote", &2));
+        assert_eq!(QuorumStatus::AlreadyReached, filter.insert(&"vote", &3));
+        thread::sleep(Duration::from_millis(60));
+        assert!(filter.is_empty());
+        assert_eq!(QuorumStatus::Pending { sources: 1 }, filter.insert(&"vote", &3));
+    }
+}
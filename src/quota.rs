@@ -0,0 +1,288 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::Duration;
+use super::hash;
+use super::timed_store::TimedStore;
+
+/// Message filter shared between several sources, each of which is limited to its own share of
+/// the entries.
+///
+/// In a plain `MessageFilter`, a single source flooding unique messages pushes out everyone
+/// else's.  Here each message is attributed to the source which first inserted it, and once a
+/// source holds its limit of entries, its own oldest entry is dropped to make room for its new
+/// one.  If the filter as a whole is full, the oldest entry of the source currently holding the
+/// most entries is dropped.
+///
+/// By default every source has the same limit and time to live; `set_quota` overrides these for
+/// individual sources.
+pub struct QuotaFilter<Message, Source> {
+    /// The entries, bounded by capacity here rather than by the store so that the entry dropped
+    /// can be chosen by source.
+    entries: TimedStore<QuotaEntry>,
+    /// The number of entries held per source.
+    source_lens: HashMap<u64, usize>,
+    quotas: HashMap<u64, Quota>,
+    default_quota: Quota,
+    capacity: usize,
+    phantom: PhantomData<(Message, Source)>,
+}
+
+#[derive(Clone, Copy)]
+struct Quota {
+    limit: usize,
+    time_to_live: Option<Duration>,
+}
+
+struct QuotaEntry {
+    source: u64,
+    /// How many copies of this message have been seen before this one.
+    count: usize,
+}
+
+impl<Message: Hash, Source: Hash> QuotaFilter<Message, Source> {
+    /// Constructor for capacity based `QuotaFilter` holding up to `capacity` messages, of which
+    /// each source may hold up to `source_limit`.
+    pub fn with_capacity(capacity: usize, source_limit: usize) -> QuotaFilter<Message, Source> {
+        QuotaFilter::new(capacity, source_limit, None)
+    }
+
+    /// Constructor for dual-feature capacity and time based `QuotaFilter` holding up to `capacity`
+    /// messages, of which each source may hold up to `source_limit`.
+    pub fn with_expiry_duration_and_capacity(time_to_live: Duration,
+                                             capacity: usize,
+                                             source_limit: usize)
+                                             -> QuotaFilter<Message, Source> {
+        QuotaFilter::new(capacity, source_limit, Some(time_to_live))
+    }
+
+    /// Sets the limit and time to live for messages from `source`, in place of the defaults.
+    ///
+    /// This only applies to messages inserted from now on.
+    pub fn set_quota(&mut self,
+                     source: &Source,
+                     limit: usize,
+                     time_to_live: Option<Duration>) {
+        let _ = self.quotas.insert(hash(source),
+                                   Quota {
+                                       limit: cmp::max(limit, 1),
+                                       time_to_live: time_to_live,
+                                   });
+    }
+
+    /// Adds a message from `source` to the filter.
+    ///
+    /// Removes any expired messages, then adds `message`, then removes the oldest of `source`'s
+    /// messages if it's over its limit, or failing that the oldest message of the source holding
+    /// the most if the filter is over capacity.  If `message` already exists in the filter, its
+    /// expiry time is updated and it is moved to the back of the FIFO queue again, but it remains
+    /// attributed to the source which first inserted it.
+    ///
+    /// The return value is the number of times this specific message has already been added, by
+    /// any source.
+    pub fn insert(&mut self, message: &Message, source: &Source) -> usize {
+        self.remove_expired();
+        let hash_code = hash(message);
+        if let Some(index) = self.entries.position(hash_code) {
+            let time_to_live = self.quota(self.entries.value(index).source).time_to_live;
+            let index = self.entries.refresh_with_expiry(index, time_to_live);
+            let entry = self.entries.value_mut(index);
+            entry.count += 1;
+            return entry.count;
+        }
+
+        let source = hash(source);
+        let entry = QuotaEntry {
+            source: source,
+            count: 0,
+        };
+        let quota = self.quota(source);
+        let _ = self.entries.push_with_expiry(hash_code, entry, quota.time_to_live);
+        *self.source_lens.entry(source).or_insert(0) += 1;
+
+        if self.source_len_by_hash(source) > quota.limit {
+            self.remove_oldest_of(source);
+        } else if self.entries.len() > self.capacity {
+            let largest = self.source_lens
+                .iter()
+                .max_by_key(|&(_, &len)| len)
+                .map(|(&largest, _)| largest);
+            if let Some(largest) = largest {
+                self.remove_oldest_of(largest);
+            }
+        }
+        0
+    }
+
+    /// Removes any expired messages, then removes the specified message from the filter.
+    pub fn remove(&mut self, message: &Message) {
+        self.remove_expired();
+        if let Some(index) = self.entries.position(hash(message)) {
+            let entry = self.entries.remove_at(index);
+            self.decrement_source_len(entry.source);
+        }
+    }
+
+    /// Returns the number of times this message has already been inserted.
+    pub fn count(&self, message: &Message) -> usize {
+        self.entries.get(hash(message)).map_or(0, |entry| entry.count)
+    }
+
+    /// Removes any expired messages, then returns whether `message` exists in the filter or not.
+    pub fn contains(&mut self, message: &Message) -> bool {
+        self.remove_expired();
+        self.entries.position(hash(message)).is_some()
+    }
+
+    /// Returns the number of messages attributed to `source`.
+    pub fn source_len(&self, source: &Source) -> usize {
+        self.source_len_by_hash(hash(source))
+    }
+
+    /// Returns the size of the filter, i.e. the number of added messages.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether there are no entries in the filter.
+    pub fn is_empty(&self) -> bool {
+        self.entries.len() == 0
+    }
+
+    /// Clears the filter, removing all entries.  Quotas set via `set_quota` are kept.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.source_lens.clear();
+    }
+
+    /// Removes any expired messages.
+    pub fn remove_expired(&mut self) {
+        for entry in self.entries.take_expired() {
+            self.decrement_source_len(entry.source);
+        }
+    }
+
+    fn new(capacity: usize,
+           source_limit: usize,
+           time_to_live: Option<Duration>)
+           -> QuotaFilter<Message, Source> {
+        QuotaFilter {
+            entries: TimedStore::new(None, time_to_live),
+            source_lens: HashMap::new(),
+            quotas: HashMap::new(),
+            default_quota: Quota {
+                limit: cmp::max(source_limit, 1),
+                time_to_live: time_to_live,
+            },
+            capacity: cmp::max(capacity, 1),
+            phantom: PhantomData,
+        }
+    }
+
+    fn quota(&self, source: u64) -> Quota {
+        self.quotas.get(&source).cloned().unwrap_or(self.default_quota)
+    }
+
+    fn source_len_by_hash(&self, source: u64) -> usize {
+        self.source_lens.get(&source).cloned().unwrap_or(0)
+    }
+
+    fn remove_oldest_of(&mut self, source: u64) {
+        if let Some(index) = self.entries.position_where(|_, entry| entry.source == source) {
+            let _ = self.entries.remove_at(index);
+            self.decrement_source_len(source);
+        }
+    }
+
+    fn decrement_source_len(&mut self, source: u64) {
+        let now_empty = match self.source_lens.get_mut(&source) {
+            Some(len) => {
+                *len -= 1;
+                *len == 0
+            }
+            None => false,
+        };
+        if now_empty {
+            let _ = self.source_lens.remove(&source);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn flooding_source_evicts_its_own_entries() {
+        let mut filter = QuotaFilter::<u32, &str>::with_capacity(100, 10);
+        for message in 0..5 {
+            assert_eq!(0, filter.insert(&message, &"good"));
+        }
+        for message in 1000..2000 {
+            assert_eq!(0, filter.insert(&message, &"bad"));
+        }
+        assert!((0..5).all(|message| filter.contains(&message)));
+        assert!((1990..2000).all(|message| filter.contains(&message)));
+        assert_eq!(10, filter.source_len(&"bad"));
+        assert_eq!(15, filter.len());
+
+        // A repeat from another source still counts, but the message stays with "good".
+        assert_eq!(1, filter.insert(&0, &"bad"));
+        assert_eq!(5, filter.source_len(&"good"));
+        filter.remove(&0);
+        assert_eq!(4, filter.source_len(&"good"));
+    }
+
+    #[test]
+    fn full_filter_evicts_from_largest_source() {
+        let mut filter = QuotaFilter::<u32, u32>::with_capacity(10, 6);
+        for message in 0..6 {
+            let _ = filter.insert(&message, &0);
+        }
+        for message in 100..104 {
+            let _ = filter.insert(&message, &1);
+        }
+        assert_eq!(10, filter.len());
+        let _ = filter.insert(&200, &2);
+        assert_eq!(10, filter.len());
+        assert!(!filter.contains(&0));
+        assert!(filter.contains(&100));
+        assert_eq!(5, filter.source_len(&0));
+    }
+
+    #[test]
+    fn per_source_time_to_live() {
+        let mut filter =
+            QuotaFilter::<u32, u32>::with_expiry_duration_and_capacity(Duration::from_secs(60),
+                                                                       10,
+                                                                       10);
+        filter.set_quota(&1, 10, Some(Duration::from_millis(50)));
+        let _ = filter.insert(&0, &0);
+        let _ = filter.insert(&1, &1);
+        thread::sleep(Duration::from_millis(60));
+        assert!(filter.contains(&0));
+        assert!(!filter.contains(&1));
+        assert_eq!(0, filter.source_len(&1));
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use super::hash;
+use super::timed_store::TimedStore;
+
+/// The outcome of `RateLimiter::check`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// The request is within the key's limit and has been counted against it.
+    Allow,
+    /// The key has exceeded its limit.
+    Deny {
+        /// How long until a request for this key would be allowed.
+        retry_after: Duration,
+    },
+}
+
+/// Keyed rate limiter, using the generic cell rate algorithm.
+///
+/// Each key may make up to `limit` requests in a burst, after which requests are allowed at a
+/// steady rate of `limit` per `period`.  The state per key is a single timestamp, and keys are
+/// forgotten once they have been idle for `period`, at which point they would have their full
+/// burst available again anyway.
+///
+/// As with `MessageFilter`, the number of keys tracked is bounded by `capacity`: once full, the
+/// least recently allowed key is forgotten.  A forgotten key starts afresh with its full burst,
+/// so the capacity should comfortably exceed the number of keys expected to be active at once.
+pub struct RateLimiter<Key> {
+    /// The theoretical arrival time of the next request for each key.
+    arrival_times: TimedStore<Instant>,
+    emission_interval: Duration,
+    period: Duration,
+    phantom: PhantomData<Key>,
+}
+
+impl<Key: Hash> RateLimiter<Key> {
+    /// Constructor for a `RateLimiter` tracking up to `capacity` keys, each allowed `limit`
+    /// requests per `period`.
+    pub fn new(capacity: usize, limit: u32, period: Duration) -> RateLimiter<Key> {
+        RateLimiter {
+            arrival_times: TimedStore::new(Some(capacity), Some(period)),
+            emission_interval: period / cmp::max(limit, 1),
+            period: period,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Checks whether a request for `key` is allowed, counting it against the key's limit if so.
+    pub fn check(&mut self, key: &Key) -> Decision {
+        let hash_code = hash(key);
+        let now = Instant::now();
+        let arrival_time = match self.arrival_times.get_mut(hash_code) {
+            Some(&mut arrival_time) if arrival_time > now => arrival_time,
+            _ => now,
+        };
+        let next_arrival_time = arrival_time + self.emission_interval;
+        let allowed_from = now + self.period;
+        if next_arrival_time > allowed_from {
+            return Decision::Deny { retry_after: next_arrival_time.duration_since(allowed_from) };
+        }
+        let _ = self.arrival_times.insert(hash_code, next_arrival_time);
+        Decision::Allow
+    }
+
+    /// Returns the number of keys currently being tracked.
+    pub fn len(&mut self) -> usize {
+        self.arrival_times.remove_expired();
+        self.arrival_times.len()
+    }
+
+    /// Returns whether no keys are being tracked.
+    pub fn is_empty(&mut self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clears the limiter, giving every key its full burst again.
+    pub fn clear(&mut self) {
+        self.arrival_times.clear();
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn burst_then_steady_rate() {
+        let period = Duration::from_millis(400);
+        let mut limiter = RateLimiter::<u8>::new(10, 4, period);
+        for _ in 0..4 {
+            assert_eq!(Decision::Allow, limiter.check(&0));
+        }
+        match limiter.check(&0) {
+            Decision::Deny { retry_after } => assert!(retry_after <= Duration::from_millis(100)),
+            Decision::Allow => panic!("Burst limit exceeded."),
+        }
+        // Other keys are unaffected.
+        assert_eq!(Decision::Allow, limiter.check(&1));
+
+        // One request's worth of time later, exactly one more request is allowed.
+        thread::sleep(Duration::from_millis(110));
+        assert_eq!(Decision::Allow, limiter.check(&0));
+        assert!(limiter.check(&0) != Decision::Allow);
+
+        // Once idle for the whole period, keys are forgotten.
+        thread::sleep(period);
+        assert!(limiter.is_empty());
+    }
+
+    #[test]
+    fn keys_are_bounded() {
+        let mut limiter = RateLimiter::<u32>::new(10, 1, Duration::from_secs(60));
+        for key in 0..100 {
+            assert_eq!(Decision::Allow, limiter.check(&key));
+        }
+        assert_eq!(10, limiter.len());
+        assert!(limiter.check(&99) != Decision::Allow);
+        // The least recently allowed keys have been forgotten.
+        assert_eq!(Decision::Allow, limiter.check(&0));
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+#[cfg(feature = "async")]
+use futures::{Future, Stream};
+#[cfg(feature = "async")]
+use futures::sync::oneshot;
+#[cfg(feature = "async")]
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+#[cfg(feature = "async")]
+use tokio_core::reactor::{Handle, Interval};
+use super::MessageFilter;
+
+/// Background thread which periodically removes expired messages from a shared `MessageFilter`.
+///
+/// Expired messages are otherwise only removed when the filter is used, so an idle time based
+/// filter would keep hold of all its entries indefinitely.  The thread is stopped and joined when
+/// the `Reaper` is dropped.
+pub struct Reaper {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Reaper {
+    /// Starts a thread which calls `remove_expired` on `filter` once every `interval`.
+    pub fn spawn<Message>(filter: Arc<Mutex<MessageFilter<Message>>>, interval: Duration) -> Reaper
+        where Message: Send + 'static
+    {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stopped = stopped.clone();
+        let thread = thread::spawn(move || {
+            while wait_for_interval(&thread_stopped, interval) {
+                match filter.lock() {
+                    Ok(mut filter) => filter.remove_expired(),
+                    Err(_) => return,
+                }
+            }
+        });
+        Reaper {
+            stopped: stopped,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Reaper {
+    fn drop(&mut self) {
+        {
+            let &(ref lock, ref condvar) = &*self.stopped;
+            if let Ok(mut stopped) = lock.lock() {
+                *stopped = true;
+            }
+            condvar.notify_one();
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Blocks for `interval`, returning early with `false` if the `Reaper` has been stopped.
+fn wait_for_interval(stopped: &(Mutex<bool>, Condvar), interval: Duration) -> bool {
+    let &(ref lock, ref condvar) = stopped;
+    let deadline = Instant::now() + interval;
+    let mut guard = match lock.lock() {
+        Ok(guard) => guard,
+        Err(_) => return false,
+    };
+    // Loop to guard against spurious wakeups.
+    while !*guard {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        guard = match condvar.wait_timeout(guard, deadline - now) {
+            Ok((guard, _)) => guard,
+            Err(_) => return false,
+        };
+    }
+    false
+}
+
+/// Task on a `tokio_core` event loop which periodically removes expired messages from a shared
+/// `MessageFilter`.
+///
+/// This is the asynchronous counterpart of `Reaper`.  The task finishes when the `AsyncReaper` is
+/// dropped.
+#[cfg(feature = "async")]
+pub struct AsyncReaper {
+    _stop: oneshot::Sender<()>,
+}
+
+#[cfg(feature = "async")]
+impl AsyncReaper {
+    /// Spawns a task onto the event loop behind `handle` which calls `remove_expired` on `filter`
+    /// once every `interval`.
+    pub fn spawn<Message>(handle: &Handle,
+                          filter: Arc<Mutex<MessageFilter<Message>>>,
+                          interval: Duration)
+                          -> io::Result<AsyncReaper>
+        where Message: 'static
+    {
+        let (stop_sender, stop_receiver) = oneshot::channel();
+        let reap = Interval::new(interval, handle)?.for_each(move |()| {
+            if let Ok(mut filter) = filter.lock() {
+                filter.remove_expired();
+            }
+            Ok(())
+        });
+        // Dropping the sender cancels the receiver, which completes the task.
+        let stop = stop_receiver.then(|_| Ok(()));
+        handle.spawn(reap.select(stop).map(|_| ()).map_err(|_| ()));
+        Ok(AsyncReaper { _stop: stop_sender })
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use MessageFilter;
+
+    #[test]
+    fn reaps_idle_filter() {
+        let time_to_live = Duration::from_millis(50);
+        let filter = MessageFilter::<usize>::with_expiry_duration(time_to_live);
+        let filter = Arc::new(Mutex::new(filter));
+        for i in 0..10 {
+            let _ = filter.lock().expect("Lock poisoned.").insert(&i);
+        }
+
+        let reaper = Reaper::spawn(filter.clone(), Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(150));
+        assert!(filter.lock().expect("Lock poisoned.").is_empty());
+
+        // Dropping the reaper should stop its thread promptly rather than waiting out a long
+        // interval.
+        drop(reaper);
+        let _reaper = Reaper::spawn(filter.clone(), Duration::from_secs(3600));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_reaps_idle_filter() {
+        use tokio_core::reactor::{Core, Timeout};
+
+        let time_to_live = Duration::from_millis(50);
+        let filter = MessageFilter::<usize>::with_expiry_duration(time_to_live);
+        let filter = Arc::new(Mutex::new(filter));
+        for i in 0..10 {
+            let _ = filter.lock().expect("Lock poisoned.").insert(&i);
+        }
+
+        let mut core = Core::new().expect("Failed to create event loop.");
+        let handle = core.handle();
+        let reaper = AsyncReaper::spawn(&handle, filter.clone(), Duration::from_millis(10))
+            .expect("Failed to spawn reaper.");
+        let timeout = Timeout::new(Duration::from_millis(150), &handle)
+            .expect("Failed to create timeout.");
+        core.run(timeout).expect("Event loop failed.");
+        assert!(filter.lock().expect("Lock poisoned.").is_empty());
+
+        // Once the reaper is dropped, its task should finish and stop reaping.
+        drop(reaper);
+        let _ = filter.lock().expect("Lock poisoned.").insert(&0);
+        let timeout = Timeout::new(Duration::from_millis(100), &handle)
+            .expect("Failed to create timeout.");
+        core.run(timeout).expect("Event loop failed.");
+        assert_eq!(1, filter.lock().expect("Lock poisoned.").entries.len());
+    }
+}
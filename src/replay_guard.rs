@@ -0,0 +1,114 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::hash::Hash;
+use std::time::{Duration, SystemTime};
+use super::MessageFilter;
+
+/// The outcome of `ReplayGuard::check`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonceStatus {
+    /// The timestamp is acceptable and the nonce hasn't been seen before.
+    Accept,
+    /// The nonce has already been seen.
+    Replay,
+    /// The timestamp is further from the current time than the allowed skew.
+    Skewed,
+}
+
+/// Replay protection for messages carrying a nonce and a timestamp.
+///
+/// Messages whose timestamp is more than `max_skew` before or after the current time are
+/// rejected outright.  Otherwise the nonce is checked against, and added to, a time based
+/// `MessageFilter`.  Each nonce is held until its message's timestamp has fallen outside the skew
+/// window, after which the message is rejected as skewed anyway, so a replay can't slip through
+/// once the nonce has expired.  The memory used is bounded by the rate of messages over twice
+/// `max_skew`.
+pub struct ReplayGuard<Nonce> {
+    nonces: MessageFilter<Nonce>,
+    max_skew: Duration,
+}
+
+impl<Nonce: Hash> ReplayGuard<Nonce> {
+    /// Constructor for a `ReplayGuard` allowing timestamps up to `max_skew` either side of the
+    /// current time.
+    pub fn new(max_skew: Duration) -> ReplayGuard<Nonce> {
+        ReplayGuard {
+            // A timestamp up to `max_skew` in the future stays acceptable for twice `max_skew`.
+            nonces: MessageFilter::with_expiry_duration(max_skew * 2),
+            max_skew: max_skew,
+        }
+    }
+
+    /// Checks a message's `nonce` and `timestamp`, recording the nonce if it's accepted.
+    pub fn check(&mut self, nonce: &Nonce, timestamp: SystemTime) -> NonceStatus {
+        let skew = match timestamp.duration_since(SystemTime::now()) {
+            Ok(ahead) => ahead,
+            Err(error) => error.duration(),
+        };
+        if skew > self.max_skew {
+            NonceStatus::Skewed
+        } else if self.nonces.contains(nonce) {
+            NonceStatus::Replay
+        } else {
+            let _ = self.nonces.insert(nonce);
+            NonceStatus::Accept
+        }
+    }
+
+    /// Returns the number of nonces being held.
+    pub fn len(&mut self) -> usize {
+        self.nonces.remove_expired();
+        self.nonces.len()
+    }
+
+    /// Returns whether no nonces are being held.
+    pub fn is_empty(&mut self) -> bool {
+        self.len() == 0
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn skew_and_replay() {
+        let max_skew = Duration::from_millis(50);
+        let mut guard = ReplayGuard::<u64>::new(max_skew);
+        let now = SystemTime::now();
+        assert_eq!(NonceStatus::Accept, guard.check(&0, now));
+        assert_eq!(NonceStatus::Replay, guard.check(&0, now));
+        assert_eq!(NonceStatus::Accept, guard.check(&1, now + Duration::from_millis(40)));
+        assert_eq!(NonceStatus::Skewed, guard.check(&2, now + Duration::from_secs(1)));
+        assert_eq!(NonceStatus::Skewed, guard.check(&2, now - Duration::from_secs(1)));
+        assert_eq!(2, guard.len());
+
+        // "1" is still acceptable after "0" has become too old, so its nonce must still be held.
+        thread::sleep(Duration::from_millis(70));
+        assert_eq!(NonceStatus::Skewed, guard.check(&0, now));
+        assert_eq!(NonceStatus::Replay, guard.check(&1, now + Duration::from_millis(40)));
+
+        // Once both are too old, their nonces have been dropped.
+        thread::sleep(Duration::from_millis(100));
+        assert!(guard.is_empty());
+    }
+}
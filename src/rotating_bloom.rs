@@ -0,0 +1,219 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use super::bloom::BloomBits;
+use super::{Filter, FilterError, hash, nanos};
+
+/// Approximate time based message filter made up of a ring of Bloom filter generations.
+///
+/// Messages are added to the newest generation, and every `time_to_live / generations` the oldest
+/// generation is cleared and becomes the newest.  This gives constant memory and constant time
+/// expiry, at the cost of the time to live being approximate: a message expires between
+/// `(generations - 1) / generations` and the whole of `time_to_live` after it was last inserted.
+/// Using more generations makes expiry more precise, but since `contains` checks every
+/// generation, the false positive rate is up to `generations` times that of a single generation.
+pub struct RotatingBloomFilter<Message> {
+    generations: VecDeque<BloomBits>,
+    generation_duration: Duration,
+    rotated_at: Instant,
+    phantom: PhantomData<Message>,
+}
+
+impl<Message: Hash> RotatingBloomFilter<Message> {
+    /// Constructor for a `RotatingBloomFilter` with `generations` generations spanning
+    /// `time_to_live`, each sized to hold `expected_items_per_generation` messages with a false
+    /// positive rate of at most `false_positive_rate`.
+    pub fn new(time_to_live: Duration,
+               generations: u32,
+               expected_items_per_generation: usize,
+               false_positive_rate: f64)
+               -> RotatingBloomFilter<Message> {
+        let generations = cmp::max(generations, 1);
+        RotatingBloomFilter {
+            generations: (0..generations)
+                .map(|_| BloomBits::new(expected_items_per_generation, false_positive_rate))
+                .collect(),
+            generation_duration: time_to_live / generations,
+            rotated_at: Instant::now(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Adds a message to the filter.
+    ///
+    /// Returns `true` if the message was added, or `false` if it was probably already in one of
+    /// the live generations.  Either way the message is added to the newest generation, so as with
+    /// `MessageFilter::insert`, re-inserting a message extends its lifetime.
+    pub fn insert(&mut self, message: &Message) -> bool {
+        self.rotate();
+        let hash_code = hash(message);
+        let present = self.contains_hash_code(hash_code);
+        if let Some(newest) = self.generations.back_mut() {
+            let _ = newest.insert(hash_code);
+        }
+        !present
+    }
+
+    /// Rotates out any expired generations, then returns whether `message` is probably in the
+    /// filter or definitely isn't.
+    pub fn contains(&mut self, message: &Message) -> bool {
+        self.rotate();
+        self.contains_hash_code(hash(message))
+    }
+
+    /// Returns the probability that `contains` reports a message which was never inserted, based
+    /// on the proportion of bits currently set in each generation.
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        1.0 -
+        self.generations
+            .iter()
+            .fold(1.0, |product, generation| {
+                product * (1.0 - generation.estimated_false_positive_rate())
+            })
+    }
+
+    /// Returns the approximate number of messages added across all live generations.  A message
+    /// re-inserted after its generation has rotated is counted once in each generation holding it.
+    pub fn len(&self) -> usize {
+        self.generations.iter().fold(0, |len, generation| len + generation.len)
+    }
+
+    /// Returns whether there are no entries in the filter.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clears the filter, removing all entries.
+    pub fn clear(&mut self) {
+        for generation in &mut self.generations {
+            generation.clear();
+        }
+        self.rotated_at = Instant::now();
+    }
+
+    fn contains_hash_code(&self, hash_code: u64) -> bool {
+        // Generations which are due to be rotated out but haven't been yet are skipped.
+        let pending = cmp::min(self.pending_rotations(), self.generations.len() as u64);
+        self.generations
+            .iter()
+            .skip(pending as usize)
+            .any(|generation| generation.contains(hash_code))
+    }
+
+    fn pending_rotations(&self) -> u64 {
+        nanos(self.rotated_at.elapsed()) / cmp::max(nanos(self.generation_duration), 1)
+    }
+
+    fn rotate(&mut self) {
+        let due = self.pending_rotations();
+        if due == 0 {
+            return;
+        }
+        let generations = self.generations.len() as u64;
+        if due >= generations {
+            // Rotating more than once around the ring would only clear already-cleared
+            // generations, so just start afresh.
+            for generation in &mut self.generations {
+                generation.clear();
+            }
+            self.rotated_at = Instant::now();
+            return;
+        }
+        for _ in 0..due {
+            if let Some(mut oldest) = self.generations.pop_front() {
+                oldest.clear();
+                self.generations.push_back(oldest);
+            }
+        }
+        self.rotated_at += self.generation_duration * due as u32;
+    }
+}
+
+
+impl<Message: Hash> Filter<Message> for RotatingBloomFilter<Message> {
+    fn insert(&mut self, message: &Message) -> Result<usize, FilterError> {
+        Ok(if RotatingBloomFilter::insert(self, message) { 0 } else { 1 })
+    }
+
+    fn contains(&mut self, message: &Message) -> bool {
+        RotatingBloomFilter::contains(self, message)
+    }
+
+    fn len(&self) -> usize {
+        RotatingBloomFilter::len(self)
+    }
+
+    fn clear(&mut self) {
+        RotatingBloomFilter::clear(self)
+    }
+
+    fn supports_expiry(&self) -> bool {
+        true
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn generations_expire() {
+        let time_to_live = Duration::from_millis(200);
+        let mut filter = RotatingBloomFilter::<usize>::new(time_to_live, 4, 100, 0.01);
+        assert!(filter.insert(&0));
+        assert!(!filter.insert(&0));
+        assert!(filter.contains(&0));
+
+        // After half the time to live, "0" is still live; insert "1" into a newer generation.
+        thread::sleep(Duration::from_millis(100));
+        assert!(filter.contains(&0));
+        assert!(filter.insert(&1));
+
+        // Once "0" has been rotated out, "1" remains.
+        thread::sleep(Duration::from_millis(110));
+        assert!(!filter.contains(&0));
+        assert!(filter.contains(&1));
+
+        // Long after the time to live, everything has expired.
+        thread::sleep(Duration::from_millis(500));
+        assert!(!filter.contains(&1));
+        assert!(filter.is_empty());
+    }
+
+    #[test]
+    fn long_idle() {
+        // Simulate being idle for more than 2^32 generations.
+        let time_to_live = Duration::from_millis(200);
+        let mut filter = RotatingBloomFilter::<usize>::new(time_to_live, 4, 100, 0.01);
+        filter.generation_duration = Duration::new(0, 1);
+        filter.rotated_at = Instant::now() - Duration::from_secs(5);
+        assert!(filter.insert(&0));
+        assert!(filter.rotated_at.elapsed() < Duration::from_secs(1));
+
+        filter.generation_duration = Duration::from_secs(60);
+        assert!(filter.contains(&0));
+    }
+}
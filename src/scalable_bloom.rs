@@ -0,0 +1,172 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use super::bloom::BloomBits;
+use super::{Filter, FilterError, hash};
+
+/// Each new stage holds this many times as many messages as the previous one.
+const GROWTH_FACTOR: usize = 2;
+/// Each new stage's false positive rate is this many times that of the previous one.
+const TIGHTENING_RATIO: f64 = 0.9;
+
+/// Approximate message filter which grows as messages are inserted, backed by a scalable Bloom
+/// filter.
+///
+/// This starts as a single Bloom filter sized for `initial_capacity` messages.  Once that's full,
+/// a further filter twice the size is added, and so on.  Each new filter is given a tighter false
+/// positive rate than the last so that the rate across all of them stays below the configured
+/// bound however far the filter grows.  As with `BloomMessageFilter`, messages can't be removed
+/// and don't expire.
+pub struct ScalableBloomFilter<Message> {
+    stages: Vec<BloomBits>,
+    initial_capacity: usize,
+    false_positive_rate: f64,
+    phantom: PhantomData<Message>,
+}
+
+impl<Message: Hash> ScalableBloomFilter<Message> {
+    /// Constructor for a `ScalableBloomFilter` initially sized for `initial_capacity` messages,
+    /// whose false positive rate will stay below `false_positive_rate`.
+    pub fn new(initial_capacity: usize, false_positive_rate: f64) -> ScalableBloomFilter<Message> {
+        assert!(false_positive_rate > 0.0 && false_positive_rate < 1.0,
+                "The false positive rate must be between 0 and 1 exclusive.");
+        let mut filter = ScalableBloomFilter {
+            stages: vec![],
+            initial_capacity: cmp::max(initial_capacity, 1),
+            false_positive_rate: false_positive_rate,
+            phantom: PhantomData,
+        };
+        filter.add_stage();
+        filter
+    }
+
+    /// Adds a message to the filter, growing it first if the newest stage is full.
+    ///
+    /// Returns `true` if the message was added, or `false` if it was probably already in the
+    /// filter.
+    pub fn insert(&mut self, message: &Message) -> bool {
+        let hash_code = hash(message);
+        if self.stages.iter().any(|stage| stage.contains(hash_code)) {
+            return false;
+        }
+        if self.stages.last().map_or(true, |stage| stage.len >= stage.capacity) {
+            self.add_stage();
+        }
+        if let Some(stage) = self.stages.last_mut() {
+            let _ = stage.insert(hash_code);
+        }
+        true
+    }
+
+    /// Returns whether `message` is probably in the filter or definitely isn't.
+    pub fn contains(&self, message: &Message) -> bool {
+        let hash_code = hash(message);
+        self.stages.iter().any(|stage| stage.contains(hash_code))
+    }
+
+    /// Returns the probability that `contains` reports a message which was never inserted, based
+    /// on the proportion of bits currently set in each stage.
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        1.0 -
+        self.stages
+            .iter()
+            .fold(1.0,
+                  |product, stage| product * (1.0 - stage.estimated_false_positive_rate()))
+    }
+
+    /// Returns the approximate number of distinct messages added.
+    pub fn len(&self) -> usize {
+        self.stages.iter().fold(0, |len, stage| len + stage.len)
+    }
+
+    /// Returns whether there are no entries in the filter.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of messages the filter can currently hold before it next grows.
+    pub fn capacity(&self) -> usize {
+        self.stages.iter().fold(0, |capacity, stage| capacity + stage.capacity)
+    }
+
+    /// Clears the filter, removing all entries and shrinking it back to its initial size.
+    pub fn clear(&mut self) {
+        self.stages.truncate(1);
+        if let Some(stage) = self.stages.first_mut() {
+            stage.clear();
+        }
+    }
+
+    fn add_stage(&mut self) {
+        // The rates form a geometric series summing to `false_positive_rate`.
+        let stage_count = self.stages.len();
+        let capacity = self.initial_capacity * GROWTH_FACTOR.pow(stage_count as u32);
+        let false_positive_rate = self.false_positive_rate * (1.0 - TIGHTENING_RATIO) *
+                                  TIGHTENING_RATIO.powi(stage_count as i32);
+        self.stages.push(BloomBits::new(capacity, false_positive_rate));
+    }
+}
+
+
+impl<Message: Hash> Filter<Message> for ScalableBloomFilter<Message> {
+    fn insert(&mut self, message: &Message) -> Result<usize, FilterError> {
+        Ok(if ScalableBloomFilter::insert(self, message) { 0 } else { 1 })
+    }
+
+    fn contains(&mut self, message: &Message) -> bool {
+        ScalableBloomFilter::contains(self, message)
+    }
+
+    fn len(&self) -> usize {
+        ScalableBloomFilter::len(self)
+    }
+
+    fn clear(&mut self) {
+        ScalableBloomFilter::clear(self)
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn grows_within_bound() {
+        let mut filter = ScalableBloomFilter::<usize>::new(100, 0.01);
+        assert_eq!(100, filter.capacity());
+        // A few insertions may be false positives, which aren't added.
+        let added = (0..10_000).filter(|i| filter.insert(i)).count();
+        assert!(added > 9900, "only {} added", added);
+        assert_eq!(added, filter.len());
+        assert!(filter.capacity() >= added);
+
+        // There are no false negatives, and the false positive rate is within the bound.
+        assert!((0..10_000).all(|i| filter.contains(&i)));
+        assert!(filter.estimated_false_positive_rate() < 0.01);
+        let false_positives = (10_000..110_000).filter(|i| filter.contains(i)).count();
+        assert!(false_positives < 1000, "{} false positives", false_positives);
+
+        filter.clear();
+        assert!(filter.is_empty());
+        assert_eq!(100, filter.capacity());
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use super::{Filter, FilterError, hash, xorshift};
+use super::bloom::{bit_index, step};
+use super::packed::PackedArray;
+
+/// Approximate message filter for unbounded streams, backed by a stable Bloom filter.
+///
+/// Each cell is a small counter rather than a single bit.  Inserting a message first decrements a
+/// fixed number of randomly chosen cells, then sets the message's cells to their maximum value.
+/// Old messages are thereby gradually forgotten without any per-message bookkeeping, and the
+/// proportion of set cells (and hence the false positive rate) converges to a fixed bound however
+/// many messages are inserted.  In exchange, a message may be forgotten before it would have been
+/// pushed out of a `MessageFilter` of comparable size, i.e. there can be false negatives.
+pub struct StableBloomFilter<Message> {
+    cells: PackedArray,
+    hash_count: usize,
+    decrement_count: usize,
+    rng_state: u64,
+    phantom: PhantomData<Message>,
+}
+
+impl<Message: Hash> StableBloomFilter<Message> {
+    /// Constructor for a `StableBloomFilter` of `cell_count` cells, each `bits_per_cell` wide
+    /// (between 1 and 32), whose false positive rate converges to `false_positive_rate`.
+    ///
+    /// More cells keep messages for longer, and wider cells trade memory for fewer false negatives
+    /// amongst recent messages.
+    pub fn new(cell_count: usize,
+               bits_per_cell: usize,
+               false_positive_rate: f64)
+               -> StableBloomFilter<Message> {
+        assert!(false_positive_rate > 0.0 && false_positive_rate < 1.0,
+                "The false positive rate must be between 0 and 1 exclusive.");
+        let cell_count = cmp::max(cell_count, 2);
+        let cells = PackedArray::new(cell_count, bits_per_cell);
+        let hash_count = cmp::min((1.0 / false_positive_rate).log2().ceil() as usize, cell_count);
+        let hash_count = cmp::max(hash_count, 1);
+
+        // From Deng and Rafiei's "Approximately Detecting Duplicates for Streaming Data using
+        // Stable Bloom Filters": the number of cells to decrement per insertion so that the
+        // proportion of zeroed cells settles where the false positive rate is as requested.
+        let max_value = cells.max_value() as f64;
+        let sub_denominator = (1.0 - false_positive_rate.powf(1.0 / hash_count as f64))
+            .powf(1.0 / max_value);
+        let denominator = (1.0 / sub_denominator - 1.0) *
+                          (1.0 / hash_count as f64 - 1.0 / cell_count as f64);
+        let decrement_count = cmp::min(cmp::max((1.0 / denominator) as usize, 1), cell_count);
+
+        StableBloomFilter {
+            cells: cells,
+            hash_count: hash_count,
+            decrement_count: decrement_count,
+            rng_state: 0x9e37_79b9_7f4a_7c15,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Adds a message to the filter.
+    ///
+    /// Returns `true` if the message was added, or `false` if it was probably already in the
+    /// filter.
+    pub fn insert(&mut self, message: &Message) -> bool {
+        let hash_code = hash(message);
+        let present = self.contains_hash_code(hash_code);
+
+        let cell_count = self.cells.len();
+        let start = (xorshift(&mut self.rng_state) % cell_count as u64) as usize;
+        for offset in 0..self.decrement_count {
+            let index = (start + offset) % cell_count;
+            let value = self.cells.get(index);
+            if value > 0 {
+                self.cells.set(index, value - 1);
+            }
+        }
+
+        let step = step(hash_code);
+        let max_value = self.cells.max_value();
+        for i in 0..self.hash_count {
+            self.cells.set(bit_index(hash_code, step, i, cell_count), max_value);
+        }
+        !present
+    }
+
+    /// Returns whether `message` is probably in the filter or probably isn't.
+    pub fn contains(&self, message: &Message) -> bool {
+        self.contains_hash_code(hash(message))
+    }
+
+    /// Returns the false positive rate which the filter converges to once it has become stable.
+    pub fn stable_false_positive_rate(&self) -> f64 {
+        let sub_denominator = self.decrement_count as f64 *
+                              (1.0 / self.hash_count as f64 - 1.0 / self.cells.len() as f64);
+        let stable_zeroed = (1.0 / (1.0 + 1.0 / sub_denominator))
+            .powf(self.cells.max_value() as f64);
+        (1.0 - stable_zeroed).powi(self.hash_count as i32)
+    }
+
+    /// Returns an estimate of the number of distinct messages currently remembered, based on the
+    /// number of non-zero cells.
+    pub fn len(&self) -> usize {
+        let cell_count = self.cells.len() as f64;
+        let set = (0..self.cells.len()).filter(|&index| self.cells.get(index) != 0).count();
+        if set == self.cells.len() {
+            return self.cells.len();
+        }
+        (-(cell_count / self.hash_count as f64) * (1.0 - set as f64 / cell_count).ln())
+            .round() as usize
+    }
+
+    /// Returns whether there are no entries in the filter.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Clears the filter, removing all entries.
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    fn contains_hash_code(&self, hash_code: u64) -> bool {
+        let step = step(hash_code);
+        (0..self.hash_count)
+            .all(|i| self.cells.get(bit_index(hash_code, step, i, self.cells.len())) != 0)
+    }
+}
+
+
+impl<Message: Hash> Filter<Message> for StableBloomFilter<Message> {
+    fn insert(&mut self, message: &Message) -> Result<usize, FilterError> {
+        Ok(if StableBloomFilter::insert(self, message) { 0 } else { 1 })
+    }
+
+    fn contains(&mut self, message: &Message) -> bool {
+        StableBloomFilter::contains(self, message)
+    }
+
+    fn len(&self) -> usize {
+        StableBloomFilter::len(self)
+    }
+
+    fn clear(&mut self) {
+        StableBloomFilter::clear(self)
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn false_positive_rate_is_bounded() {
+        let mut filter = StableBloomFilter::<usize>::new(10_000, 3, 0.01);
+        let stable_rate = filter.stable_false_positive_rate();
+        assert!(stable_rate > 0.005 && stable_rate <= 0.011, "stable rate {}", stable_rate);
+
+        for i in 0..100_000 {
+            let _ = filter.insert(&i);
+            assert!(filter.contains(&i));
+        }
+        // The most recent messages are still remembered.
+        assert!((99_900..100_000).all(|i| filter.contains(&i)));
+
+        // Long after the filter has become stable, the false positive rate is around the bound.
+        let false_positives = (100_000..200_000).filter(|i| filter.contains(i)).count();
+        assert!(false_positives < 2000, "{} false positives", false_positives);
+
+        filter.clear();
+        assert!(!filter.contains(&99_999));
+    }
+}
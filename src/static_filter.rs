@@ -0,0 +1,426 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::hash::Hash;
+use super::{Filter, FilterError, MessageFilter, MessageId, hash, hash_id, rehash, xorshift};
+
+/// Version of the format written by `StaticFilter::to_bytes`.
+const FORMAT_VERSION: u8 = 2;
+/// Length of the version, flags, seed and block length which precede the fingerprints when
+/// serialised.
+const HEADER_LEN: usize = 14;
+/// Flag set when messages are identified by their `MessageId`.
+const BY_ID_FLAG: u8 = 1;
+
+/// Immutable approximate set of messages, backed by an XOR filter.
+///
+/// This is built once from a fixed set of messages and can't be added to afterwards, but needs
+/// only around 10 bits per message for a false positive rate of about 0.4%.  It can be serialised
+/// so that a large set of known messages only needs to be built once, then shipped and loaded.
+///
+/// Messages are identified by the output of their `Hash` implementation (or that of their
+/// `MessageId` for a filter built via `new_by_id`).  That output can vary with pointer width,
+/// endianness and the version of Rust, so a serialised filter is only meaningful to a program
+/// using the same message type, built the same way for a platform with the same pointer width and
+/// endianness.
+pub struct StaticFilter<Message> {
+    seed: u64,
+    block_length: usize,
+    fingerprints: Vec<u8>,
+    by_id: bool,
+    hash_fn: fn(&Message) -> u64,
+}
+
+impl<Message: Hash> StaticFilter<Message> {
+    /// Builds a `StaticFilter` containing `messages`.
+    pub fn new<'a, I>(messages: I) -> StaticFilter<Message>
+        where I: IntoIterator<Item = &'a Message>,
+              Message: 'a
+    {
+        StaticFilter::build(messages, false, hash::<Message>)
+    }
+
+    /// Reads a `StaticFilter` previously written by `to_bytes`.
+    ///
+    /// Returns `FilterError::InvalidEncoding` if the filter was built via `new_by_id`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<StaticFilter<Message>, FilterError> {
+        StaticFilter::parse(bytes, false, hash::<Message>)
+    }
+}
+
+impl<Message: MessageId> StaticFilter<Message> {
+    /// Builds a `StaticFilter` containing `messages`, which identifies messages by their
+    /// `MessageId`.
+    pub fn new_by_id<'a, I>(messages: I) -> StaticFilter<Message>
+        where I: IntoIterator<Item = &'a Message>,
+              Message: 'a
+    {
+        StaticFilter::build(messages, true, hash_id::<Message>)
+    }
+
+    /// Reads a `StaticFilter` previously written by `to_bytes`, which identifies messages by their
+    /// `MessageId`.
+    ///
+    /// Returns `FilterError::InvalidEncoding` if the filter wasn't built via `new_by_id`.
+    pub fn from_bytes_by_id(bytes: &[u8]) -> Result<StaticFilter<Message>, FilterError> {
+        StaticFilter::parse(bytes, true, hash_id::<Message>)
+    }
+}
+
+impl<Message> StaticFilter<Message> {
+    /// Serialises the filter, for reading back via `from_bytes` (or `from_bytes_by_id`).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // The format holds the block length in 32 bits, enough for about 10 billion messages.
+        assert!(self.block_length as u64 <= ::std::u32::MAX as u64,
+                "StaticFilter is too large to serialise");
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.fingerprints.len());
+        bytes.push(FORMAT_VERSION);
+        bytes.push(if self.by_id { BY_ID_FLAG } else { 0 });
+        bytes.extend((0..8).map(|i| (self.seed >> (8 * i)) as u8));
+        bytes.extend((0..4).map(|i| (self.block_length >> (8 * i)) as u8));
+        bytes.extend_from_slice(&self.fingerprints);
+        bytes
+    }
+
+    /// Returns whether `message` is probably in the filter or definitely isn't.
+    pub fn contains(&self, message: &Message) -> bool {
+        self.contains_hash_code((self.hash_fn)(message))
+    }
+
+    fn contains_hash_code(&self, hash_code: u64) -> bool {
+        let mixed = rehash(hash_code, self.seed);
+        let (first, second, third) = slots(mixed, self.block_length);
+        fingerprint(mixed) ==
+        self.fingerprints[first] ^ self.fingerprints[second] ^ self.fingerprints[third]
+    }
+
+    fn build<'a, I>(messages: I, by_id: bool, hash_fn: fn(&Message) -> u64) -> StaticFilter<Message>
+        where I: IntoIterator<Item = &'a Message>,
+              Message: 'a
+    {
+        let mut hash_codes = messages.into_iter().map(hash_fn).collect::<Vec<_>>();
+        // Duplicates would prevent the construction from ever succeeding.
+        hash_codes.sort();
+        hash_codes.dedup();
+
+        let capacity = 32 + hash_codes.len() * 123 / 100;
+        let block_length = capacity / 3;
+        let mut seed_state = 0x5851_f42d_4c95_7f2d;
+        loop {
+            let seed = xorshift(&mut seed_state);
+            if let Some(fingerprints) = build(&hash_codes, seed, block_length) {
+                return StaticFilter {
+                    seed: seed,
+                    block_length: block_length,
+                    fingerprints: fingerprints,
+                    by_id: by_id,
+                    hash_fn: hash_fn,
+                };
+            }
+        }
+    }
+
+    fn parse(bytes: &[u8],
+             by_id: bool,
+             hash_fn: fn(&Message) -> u64)
+             -> Result<StaticFilter<Message>, FilterError> {
+        let flags = if by_id { BY_ID_FLAG } else { 0 };
+        if bytes.len() < HEADER_LEN || bytes[0] != FORMAT_VERSION || bytes[1] != flags {
+            return Err(FilterError::InvalidEncoding);
+        }
+        let seed = (0..8).fold(0u64, |seed, i| seed | (bytes[2 + i] as u64) << (8 * i));
+        let block_length = (0..4)
+            .fold(0u64, |length, i| length | (bytes[10 + i] as u64) << (8 * i)) as usize;
+        // The block length is untrusted, so mustn't be allowed to overflow.
+        match block_length.checked_mul(3) {
+            Some(len) if block_length != 0 && bytes.len() - HEADER_LEN == len => (),
+            _ => return Err(FilterError::InvalidEncoding),
+        }
+        Ok(StaticFilter {
+            seed: seed,
+            block_length: block_length,
+            fingerprints: bytes[HEADER_LEN..].to_vec(),
+            by_id: by_id,
+            hash_fn: hash_fn,
+        })
+    }
+}
+
+/// Tries to build the fingerprints for `hash_codes` using `seed`, returning `None` if the
+/// resulting hypergraph can't be peeled.
+fn build(hash_codes: &[u64], seed: u64, block_length: usize) -> Option<Vec<u8>> {
+    let capacity = 3 * block_length;
+    let mut counts = vec![0usize; capacity];
+    let mut xor_masks = vec![0u64; capacity];
+    for &hash_code in hash_codes {
+        let mixed = rehash(hash_code, seed);
+        let (first, second, third) = slots(mixed, block_length);
+        for &slot in &[first, second, third] {
+            counts[slot] += 1;
+            xor_masks[slot] ^= mixed;
+        }
+    }
+
+    // Repeatedly remove messages which are alone in one of their slots, recording that slot as
+    // the one to be assigned to them.
+    let mut queue = (0..capacity).filter(|&slot| counts[slot] == 1).collect::<Vec<_>>();
+    let mut stack = Vec::with_capacity(hash_codes.len());
+    while let Some(slot) = queue.pop() {
+        if counts[slot] != 1 {
+            continue;
+        }
+        let mixed = xor_masks[slot];
+        stack.push((mixed, slot));
+        let (first, second, third) = slots(mixed, block_length);
+        for &other in &[first, second, third] {
+            counts[other] -= 1;
+            xor_masks[other] ^= mixed;
+            if counts[other] == 1 {
+                queue.push(other);
+            }
+        }
+    }
+    if stack.len() != hash_codes.len() {
+        return None;
+    }
+
+    // Assign in reverse order of removal, so each message's slot is set after those of the
+    // messages removed after it, leaving its other two slots unchanged from then on.
+    let mut fingerprints = vec![0u8; capacity];
+    for &(mixed, slot) in stack.iter().rev() {
+        let (first, second, third) = slots(mixed, block_length);
+        fingerprints[slot] = fingerprint(mixed) ^ fingerprints[first] ^ fingerprints[second] ^
+                             fingerprints[third];
+    }
+    Some(fingerprints)
+}
+
+fn slots(mixed: u64, block_length: usize) -> (usize, usize, usize) {
+    let reduce = |hash: u64| ((hash & 0xffff_ffff) * block_length as u64 >> 32) as usize;
+    (reduce(mixed),
+     reduce(mixed.rotate_left(21)) + block_length,
+     reduce(mixed.rotate_left(42)) + 2 * block_length)
+}
+
+fn fingerprint(mixed: u64) -> u8 {
+    (mixed ^ mixed >> 32) as u8
+}
+
+/// A `StaticFilter` of known messages layered over a `MessageFilter` of recently seen ones.
+///
+/// Known messages are never added to the live filter, so they don't take up its capacity.
+pub struct LayeredFilter<Message> {
+    known: StaticFilter<Message>,
+    live: MessageFilter<Message>,
+}
+
+impl<Message> LayeredFilter<Message> {
+    /// Constructor for a `LayeredFilter` checking `known` before `live`.
+    ///
+    /// Both filters must identify messages in the same way, i.e. either both by their `MessageId`
+    /// or neither, otherwise `FilterError::KeyMismatch` is returned.
+    pub fn new(known: StaticFilter<Message>,
+               live: MessageFilter<Message>)
+               -> Result<LayeredFilter<Message>, FilterError> {
+        if known.by_id != live.by_id {
+            return Err(FilterError::KeyMismatch);
+        }
+        Ok(LayeredFilter {
+            known: known,
+            live: live,
+        })
+    }
+
+    /// Adds a message to the live filter, unless it's a known message.
+    ///
+    /// Returns `None` for a known message, otherwise the result of `MessageFilter::insert`.
+    pub fn insert(&mut self, message: &Message) -> Option<usize> {
+        if self.known.contains(message) {
+            None
+        } else {
+            Some(self.live.insert(message))
+        }
+    }
+
+    /// Returns whether `message` is either a known message or in the live filter.
+    pub fn contains(&mut self, message: &Message) -> bool {
+        self.known.contains(message) || self.live.contains(message)
+    }
+
+    /// Returns the static filter of known messages.
+    pub fn known(&self) -> &StaticFilter<Message> {
+        &self.known
+    }
+
+    /// Returns the live filter.
+    pub fn live(&self) -> &MessageFilter<Message> {
+        &self.live
+    }
+
+    /// Returns the live filter mutably.
+    pub fn live_mut(&mut self) -> &mut MessageFilter<Message> {
+        &mut self.live
+    }
+}
+
+
+/// Known messages count as having been inserted once, and can't be removed.
+impl<Message> Filter<Message> for LayeredFilter<Message> {
+    fn insert(&mut self, message: &Message) -> Result<usize, FilterError> {
+        Ok(LayeredFilter::insert(self, message).unwrap_or(1))
+    }
+
+    fn contains(&mut self, message: &Message) -> bool {
+        LayeredFilter::contains(self, message)
+    }
+
+    fn count(&self, message: &Message) -> usize {
+        if self.known.contains(message) {
+            0
+        } else {
+            self.live.count(message)
+        }
+    }
+
+    fn remove(&mut self, message: &Message) -> Result<(), FilterError> {
+        if self.known.contains(message) {
+            return Err(FilterError::Unsupported);
+        }
+        self.live.remove(message);
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.live.len()
+    }
+
+    fn clear(&mut self) {
+        self.live.clear()
+    }
+
+    fn supports_remove(&self) -> bool {
+        true
+    }
+
+    fn supports_count(&self) -> bool {
+        true
+    }
+
+    fn supports_expiry(&self) -> bool {
+        self.live.entries.time_to_live().is_some()
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use {FilterError, MessageFilter, MessageId};
+
+    #[test]
+    fn build_and_serialise() {
+        let known = (0..10_000).collect::<Vec<usize>>();
+        let filter = StaticFilter::new(&known);
+        assert!(known.iter().all(|i| filter.contains(i)));
+        let false_positives = (10_000..110_000).filter(|i| filter.contains(i)).count();
+        assert!(false_positives < 1000, "{} false positives", false_positives);
+
+        let bytes = filter.to_bytes();
+        let parsed = StaticFilter::<usize>::from_bytes(&bytes).expect("Failed to parse filter.");
+        assert!(known.iter().all(|i| parsed.contains(i)));
+        assert_eq!(bytes, parsed.to_bytes());
+
+        assert_eq!(Some(FilterError::InvalidEncoding),
+                   StaticFilter::<usize>::from_bytes(&bytes[..bytes.len() - 1]).err());
+        assert_eq!(Some(FilterError::InvalidEncoding),
+                   StaticFilter::<usize>::from_bytes(&[]).err());
+        let mut huge = bytes[..HEADER_LEN].to_vec();
+        for byte in &mut huge[10..] {
+            *byte = 0xff;
+        }
+        assert_eq!(Some(FilterError::InvalidEncoding),
+                   StaticFilter::<usize>::from_bytes(&huge).err());
+    }
+
+    #[test]
+    fn by_id() {
+        #[derive(Hash)]
+        struct Message {
+            id: u32,
+            hop_count: u8,
+        }
+
+        impl MessageId for Message {
+            type Id = u32;
+            fn message_id(&self) -> u32 {
+                self.id
+            }
+        }
+
+        let known = (0..100)
+            .map(|id| {
+                Message {
+                    id: id,
+                    hop_count: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+        let filter = StaticFilter::new_by_id(&known);
+        let bytes = filter.to_bytes();
+        assert_eq!(Some(FilterError::InvalidEncoding),
+                   StaticFilter::<Message>::from_bytes(&bytes).err());
+        let filter = StaticFilter::<Message>::from_bytes_by_id(&bytes)
+            .expect("Failed to parse filter.");
+        assert!(filter.contains(&Message {
+            id: 1,
+            hop_count: 5,
+        }));
+
+        // The live filter must identify messages the same way as the static one.
+        let live = MessageFilter::<Message>::with_capacity(10);
+        assert_eq!(Some(FilterError::KeyMismatch),
+                   LayeredFilter::new(StaticFilter::new_by_id(&known), live).err());
+        let live = MessageFilter::<Message>::with_capacity_by_id(10);
+        let mut filter = LayeredFilter::new(filter, live).expect("Filters should match.");
+        assert_eq!(None,
+                   filter.insert(&Message {
+                       id: 1,
+                       hop_count: 5,
+                   }));
+        assert_eq!(Some(0),
+                   filter.insert(&Message {
+                       id: 100,
+                       hop_count: 0,
+                   }));
+    }
+
+    #[test]
+    fn layered() {
+        let known = vec![1usize, 2, 3];
+        let mut filter = LayeredFilter::new(StaticFilter::new(&known),
+                                            MessageFilter::with_capacity(10))
+            .expect("Filters should match.");
+        assert_eq!(None, filter.insert(&1));
+        assert_eq!(Some(0), filter.insert(&4));
+        assert_eq!(Some(1), filter.insert(&4));
+        assert!(filter.contains(&2));
+        assert!(filter.contains(&4));
+        assert!(!filter.contains(&5));
+        assert_eq!(1, filter.live().len());
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use futures::{Async, Poll, Stream};
+use futures::sync::mpsc::UnboundedSender;
+use super::MessageFilter;
+
+/// Extension methods for deduplicating a `futures::Stream` via a `MessageFilter`.
+pub trait DedupStreamExt: Stream + Sized {
+    /// Wraps the stream so that it only yields items for which `filter.insert` returns 0, i.e.
+    /// items which the filter hasn't already seen.
+    fn dedup_by_filter(self, filter: MessageFilter<Self::Item>) -> DedupStream<Self>;
+}
+
+impl<S: Stream> DedupStreamExt for S {
+    fn dedup_by_filter(self, filter: MessageFilter<S::Item>) -> DedupStream<S> {
+        DedupStream {
+            stream: self,
+            filter: filter,
+            duplicates: None,
+        }
+    }
+}
+
+/// Stream returned by `DedupStreamExt::dedup_by_filter`.
+pub struct DedupStream<S: Stream> {
+    stream: S,
+    filter: MessageFilter<S::Item>,
+    duplicates: Option<UnboundedSender<S::Item>>,
+}
+
+impl<S: Stream> DedupStream<S> {
+    /// Sends items which are dropped as duplicates to `sender` rather than discarding them.  The
+    /// receiving end is itself a `Stream`, so can be consumed on the same event loop.
+    ///
+    /// If the receiving end is disconnected, duplicates are silently discarded from then on.
+    pub fn with_duplicates(mut self, sender: UnboundedSender<S::Item>) -> DedupStream<S> {
+        self.duplicates = Some(sender);
+        self
+    }
+
+    /// Returns a reference to the underlying filter.
+    pub fn filter(&self) -> &MessageFilter<S::Item> {
+        &self.filter
+    }
+
+    /// Returns a mutable reference to the underlying filter.
+    pub fn filter_mut(&mut self) -> &mut MessageFilter<S::Item> {
+        &mut self.filter
+    }
+
+    /// Consumes this adapter, returning the wrapped stream and the filter.
+    pub fn into_inner(self) -> (S, MessageFilter<S::Item>) {
+        (self.stream, self.filter)
+    }
+}
+
+impl<S: Stream> Stream for DedupStream<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        loop {
+            let item = match try_ready!(self.stream.poll()) {
+                Some(item) => item,
+                None => return Ok(Async::Ready(None)),
+            };
+            if self.filter.insert(&item) == 0 {
+                return Ok(Async::Ready(Some(item)));
+            }
+            let disconnected = self.duplicates
+                .as_ref()
+                .map_or(false, |sender| sender.unbounded_send(item).is_err());
+            if disconnected {
+                self.duplicates = None;
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{Future, Stream, stream};
+    use futures::sync::mpsc;
+    use MessageFilter;
+
+    #[test]
+    fn yields_first_occurrences() {
+        let (sender, receiver) = mpsc::unbounded();
+        let items = vec![1u8, 2, 1, 3, 2, 1, 4];
+        let deduped = stream::iter_ok::<_, ()>(items)
+            .dedup_by_filter(MessageFilter::with_capacity(10))
+            .with_duplicates(sender)
+            .collect()
+            .wait();
+        assert_eq!(Ok(vec![1, 2, 3, 4]), deduped);
+        assert_eq!(Ok(vec![1, 2, 1]), receiver.collect().wait());
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// Values keyed by hash code, bounded by capacity and/or time to live.  This is the queue behind
+/// `MessageFilter`, shared by the other filters with the same semantics.
+///
+/// Entries are held from least to most recently inserted, so once over capacity the oldest is
+/// dropped.  While every entry has the store's time to live, the expired entries are always at the
+/// front; an entry can be given a different time to live, after which expired entries are searched
+/// for throughout.
+pub struct TimedStore<Value> {
+    entries: Vec<TimedEntry<Value>>,
+    capacity: Option<usize>,
+    time_to_live: Option<Duration>,
+    /// Whether every entry has the store's time to live, so expires in insertion order.
+    in_expiry_order: bool,
+}
+
+struct TimedEntry<Value> {
+    hash_code: u64,
+    expiry_point: Option<Instant>,
+    value: Value,
+}
+
+impl<Value> TimedStore<Value> {
+    pub fn new(capacity: Option<usize>, time_to_live: Option<Duration>) -> TimedStore<Value> {
+        TimedStore {
+            entries: vec![],
+            capacity: capacity,
+            time_to_live: time_to_live,
+            in_expiry_order: true,
+        }
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    pub fn time_to_live(&self) -> Option<Duration> {
+        self.time_to_live
+    }
+
+    /// Returns the value for `hash_code` unless it has expired.
+    pub fn get(&self, hash_code: u64) -> Option<&Value> {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .find(|entry| entry.hash_code == hash_code && !entry.has_expired(now))
+            .map(|entry| &entry.value)
+    }
+
+    /// Removes any expired entries, then returns the value for `hash_code`.
+    pub fn get_mut(&mut self, hash_code: u64) -> Option<&mut Value> {
+        self.remove_expired();
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.hash_code == hash_code)
+            .map(|entry| &mut entry.value)
+    }
+
+    /// Removes any expired entries, then sets the value for `hash_code`, refreshing its expiry
+    /// point and moving it to the back of the queue.
+    ///
+    /// If this takes the store over capacity, the oldest entry is dropped.  Returns the previous
+    /// value if any, along with the index of the new entry, or `None` if the capacity is zero so
+    /// the new entry wasn't kept either.
+    pub fn insert(&mut self, hash_code: u64, value: Value) -> (Option<Value>, Option<usize>) {
+        self.remove_expired();
+        let old_value = self.position(hash_code).map(|index| self.entries.remove(index).value);
+        (old_value, self.push(hash_code, value))
+    }
+
+    /// Adds an entry for `hash_code` at the back of the queue, without checking for an existing
+    /// one.  Returns its index as for `insert`.
+    pub fn push(&mut self, hash_code: u64, value: Value) -> Option<usize> {
+        let time_to_live = self.time_to_live;
+        self.push_with_expiry(hash_code, value, time_to_live)
+    }
+
+    /// As `push`, but with the entry expiring after `time_to_live` in place of the store's.
+    pub fn push_with_expiry(&mut self,
+                            hash_code: u64,
+                            value: Value,
+                            time_to_live: Option<Duration>)
+                            -> Option<usize> {
+        if self.capacity == Some(0) {
+            return None;
+        }
+        let expiry_point = self.expiry_point(time_to_live);
+        self.entries.push(TimedEntry {
+            hash_code: hash_code,
+            expiry_point: expiry_point,
+            value: value,
+        });
+        if let Some(capacity) = self.capacity {
+            if self.entries.len() > capacity {
+                let _ = self.entries.remove(0);
+            }
+        }
+        Some(self.entries.len() - 1)
+    }
+
+    /// Refreshes the expiry point of the entry at `index` and moves it to the back of the queue,
+    /// returning its new index.
+    pub fn refresh(&mut self, index: usize) -> usize {
+        let time_to_live = self.time_to_live;
+        self.refresh_with_expiry(index, time_to_live)
+    }
+
+    /// As `refresh`, but with the entry expiring after `time_to_live` in place of the store's.
+    pub fn refresh_with_expiry(&mut self, index: usize, time_to_live: Option<Duration>) -> usize {
+        let mut entry = self.entries.remove(index);
+        entry.expiry_point = self.expiry_point(time_to_live);
+        self.entries.push(entry);
+        self.entries.len() - 1
+    }
+
+    /// Removes any expired entries, then removes and returns the value for `hash_code`.
+    pub fn remove(&mut self, hash_code: u64) -> Option<Value> {
+        self.remove_expired();
+        self.position(hash_code).map(|index| self.entries.remove(index).value)
+    }
+
+    /// Removes any expired entries.  If they all have, their memory is released.
+    pub fn remove_expired(&mut self) {
+        let _ = self.take_expired();
+    }
+
+    /// Removes any expired entries, returning their values from oldest to newest.
+    pub fn take_expired(&mut self) -> Vec<Value> {
+        let now = Instant::now();
+        if self.in_expiry_order {
+            if self.time_to_live.is_none() {
+                return vec![];
+            }
+            let expired = self.entries.iter().take_while(|entry| entry.has_expired(now)).count();
+            return self.take_front(expired);
+        }
+        if !self.entries.iter().any(|entry| entry.has_expired(now)) {
+            return vec![];
+        }
+        let (expired, unexpired): (Vec<_>, Vec<_>) = mem::replace(&mut self.entries, vec![])
+            .into_iter()
+            .partition(|entry| entry.has_expired(now));
+        self.entries = unexpired;
+        expired.into_iter().map(|entry| entry.value).collect()
+    }
+
+    /// Removes the oldest `count` entries, returning their values from oldest to newest.
+    pub fn take_front(&mut self, count: usize) -> Vec<Value> {
+        if count == 0 {
+            return vec![];
+        }
+        let remaining = self.entries.split_off(count);
+        let taken = mem::replace(&mut self.entries, remaining);
+        taken.into_iter().map(|entry| entry.value).collect()
+    }
+
+    /// Returns the index of the entry for `hash_code`, including if it has expired but not yet
+    /// been removed.
+    pub fn position(&self, hash_code: u64) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.hash_code == hash_code)
+    }
+
+    /// Returns the index of the oldest entry for which `predicate` returns `true`, including if it
+    /// has expired but not yet been removed.
+    pub fn position_where<F>(&self, mut predicate: F) -> Option<usize>
+        where F: FnMut(u64, &Value) -> bool
+    {
+        self.entries.iter().position(|entry| predicate(entry.hash_code, &entry.value))
+    }
+
+    pub fn value(&self, index: usize) -> &Value {
+        &self.entries[index].value
+    }
+
+    pub fn value_mut(&mut self, index: usize) -> &mut Value {
+        &mut self.entries[index].value
+    }
+
+    pub fn expiry(&self, index: usize) -> Option<Instant> {
+        self.entries[index].expiry_point
+    }
+
+    pub fn remove_at(&mut self, index: usize) -> Value {
+        self.entries.remove(index).value
+    }
+
+    /// Keeps only the entries for which `predicate` returns `true`.
+    pub fn retain<F: FnMut(&Value) -> bool>(&mut self, mut predicate: F) {
+        self.entries.retain(|entry| predicate(&entry.value));
+    }
+
+    /// Returns the number of entries, including any which have expired but not yet been removed.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.in_expiry_order = true;
+    }
+
+    fn expiry_point(&mut self, time_to_live: Option<Duration>) -> Option<Instant> {
+        if time_to_live != self.time_to_live {
+            self.in_expiry_order = false;
+        }
+        time_to_live.map(|time_to_live| Instant::now() + time_to_live)
+    }
+}
+
+impl<Value> TimedEntry<Value> {
+    fn has_expired(&self, now: Instant) -> bool {
+        self.expiry_point.map_or(false, |expiry_point| expiry_point <= now)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn capacity_and_expiry() {
+        let mut store = TimedStore::new(Some(2), Some(Duration::from_millis(100)));
+        assert_eq!((None, Some(0)), store.insert(1, "a"));
+        assert_eq!((None, Some(1)), store.insert(2, "b"));
+        // Replacing "1" makes "2" the oldest, so it's dropped to make room for "3".
+        assert_eq!((Some("a"), Some(1)), store.insert(1, "c"));
+        assert_eq!((None, Some(1)), store.insert(3, "d"));
+        assert_eq!(None, store.get_mut(2));
+        assert_eq!(Some(&mut "c"), store.get_mut(1));
+        assert_eq!(2, store.len());
+
+        thread::sleep(Duration::from_millis(110));
+        assert_eq!(None, store.get_mut(1));
+        assert_eq!(0, store.len());
+        assert_eq!(0, store.entries.capacity());
+
+        // With no capacity, nothing is kept.
+        let mut store = TimedStore::new(Some(0), None);
+        assert_eq!((None, None), store.insert(1, "a"));
+        assert_eq!(0, store.len());
+    }
+    #[test]
+    fn own_expiry() {
+        let mut store = TimedStore::new(None, Some(Duration::from_secs(60)));
+        assert_eq!(Some(0), store.push(1, "a"));
+        assert_eq!(Some(1), store.push_with_expiry(2, "b", Some(Duration::from_millis(50))));
+        assert_eq!(Some(2), store.push(3, "c"));
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(vec!["b"], store.take_expired());
+        assert_eq!(Some(&"c"), store.get(3));
+        assert_eq!(1, store.refresh(0));
+        assert_eq!(Some(0), store.position(3));
+        assert_eq!(2, store.len());
+    }
+}